@@ -1,19 +1,177 @@
 use std::{
     collections::{HashMap, HashSet},
     fs,
+    io::{self, Write},
 };
 
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::clients::github::{self, PullRequestState};
+use crate::clients::sqlite::SqliteStore;
+use crate::event::{self, Event};
+use crate::feedback::CiStatus;
+use crate::settings::{self, CacheBackend};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Open,
+    Closed,
+    Merged,
+}
+
+impl From<&github::PullRequest> for State {
+    fn from(value: &github::PullRequest) -> Self {
+        if value.merged_at.is_some() {
+            Self::Merged
+        } else if value.state == PullRequestState::Closed {
+            Self::Closed
+        } else {
+            Self::Open
+        }
+    }
+}
+
+/// The last seen CI status of a commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ci {
+    pub sha: String,
+    pub status: CiStatus,
+}
+
+/// A page of a cached Github response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page {
+    pub etag: Option<String>,
+    pub len: usize,
+}
+
+/// The last response of a Github endpoint, used to make conditional requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub pages: Vec<Page>,
+    // the items of all the pages, or the single object for endpoints that are not lists. Only the
+    // fields that are parsed are kept, not the whole response.
+    pub items: Vec<Value>,
+    // the latest `updated_at` of the items, for endpoints that are fetched with `since`
+    pub since: Option<String>,
+}
+
+/// The users and teams that a PR is waiting on
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Assignments {
+    pub requested_reviewers: HashSet<String>,
+    // teams are stored as "org/team-slug"
+    pub requested_teams: HashSet<String>,
+    pub assignees: HashSet<String>,
+}
+
+/// The last seen content of a comment or review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Content {
+    pub author: String,
+    pub body: String,
+}
+
+/// The contents of comments or reviews by id. The content is `None` for ids cached by older
+/// versions, which only stored the ids.
+pub type Contents = HashMap<usize, Option<Content>>;
+
+fn deserialize_contents<'de, D>(deserializer: D) -> Result<Contents, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IdsOrContents {
+        Ids(HashSet<usize>),
+        // untagged enums cannot deserialize integer map keys directly
+        Contents(HashMap<String, Option<Content>>),
+    }
+
+    match IdsOrContents::deserialize(deserializer)? {
+        IdsOrContents::Ids(ids) => Ok(ids.into_iter().map(|x| (x, None)).collect()),
+        IdsOrContents::Contents(contents) => contents
+            .into_iter()
+            .map(|(k, v)| Ok((k.parse().map_err(serde::de::Error::custom)?, v)))
+            .collect(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullRequest {
-    pub reviews: HashSet<usize>,
-    pub comments: HashSet<usize>,
+    #[serde(deserialize_with = "deserialize_contents")]
+    pub reviews: Contents,
+    #[serde(deserialize_with = "deserialize_contents")]
+    pub comments: Contents,
+    /// The ids of the replies to review comment threads, `None` if cached before replies were
+    /// tracked
+    #[serde(default)]
+    pub replies: Option<HashSet<usize>>,
+    // whether each review thread is resolved, by the GraphQL node id of the thread
+    #[serde(default)]
+    pub threads: Option<HashMap<String, bool>>,
+    #[serde(default)]
+    pub info: Option<event::PullRequest>,
+    #[serde(default)]
+    pub state: Option<State>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub head_sha: Option<String>,
+    #[serde(default)]
+    pub commits: Vec<String>,
+    #[serde(default)]
+    pub ci: Option<Ci>,
+    #[serde(default)]
+    pub assignments: Option<Assignments>,
+    // the responses of the Github endpoints of the PR, by path
+    #[serde(default)]
+    pub responses: HashMap<String, Response>,
 }
 
 pub type Data = HashMap<usize, PullRequest>;
 
+/// The version of the format of the JSON cache. Caches written before the format was versioned
+/// are only the map of PRs, and are read as version 0.
+const FORMAT_VERSION: u64 = 1;
+
+#[derive(Serialize)]
+struct CacheFile<'a> {
+    version: u64,
+    pull_requests: &'a Data,
+}
+
+/// Keeps the state of the watched PRs between polls
+pub trait Store {
+    /// Returns the stored state, or `None` if nothing has been stored yet
+    fn read(&self) -> Result<Option<Data>>;
+
+    /// Replaces the stored state with the given one
+    fn write(&self, data: &Data) -> Result<()>;
+
+    /// Stores the state of a single PR, leaving the other PRs as they are
+    fn write_pull_request(&self, id: usize, pull_request: &PullRequest) -> Result<()>;
+
+    /// Records that an event was sent, along with the error if no notifier could send it
+    fn record_delivery(&self, _event: &Event, _error: Option<&anyhow::Error>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the configured store
+pub fn from_settings(settings: &settings::Cache) -> Result<Box<dyn Store>> {
+    match settings.backend {
+        CacheBackend::Json => Ok(Box::new(CacheClient::new(settings.path.clone()))),
+        CacheBackend::Sqlite => Ok(Box::new(SqliteStore::open(
+            &settings.path,
+            settings.import_path.as_deref(),
+        )?)),
+    }
+}
+
 pub struct CacheClient {
     filename: String,
 }
@@ -23,15 +181,218 @@ impl CacheClient {
         Self { filename }
     }
 
-    pub fn read(&self) -> Result<Data> {
-        let contents = fs::read_to_string(&self.filename)?;
-        let deserialized = serde_json::from_str::<Data>(&contents)?;
-        Ok(deserialized)
+    /// Parses the cache, in the current format or any of the older ones
+    fn parse(contents: &str) -> Result<Data> {
+        let mut value: Value = serde_json::from_str(contents)?;
+
+        let version = match value.get("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| anyhow!("Invalid cache format version {}", version))?,
+            None => 0,
+        };
+        if version > FORMAT_VERSION {
+            return Err(anyhow!(
+                "Cache format version {} is newer than the supported version {}",
+                version,
+                FORMAT_VERSION
+            ));
+        }
+
+        let pull_requests = match version {
+            0 => value,
+            _ => value
+                .get_mut("pull_requests")
+                .map(Value::take)
+                .ok_or_else(|| anyhow!("Cache is missing the pull requests"))?,
+        };
+
+        Ok(serde_json::from_value(pull_requests)?)
+    }
+}
+
+impl Store for CacheClient {
+    fn read(&self) -> Result<Option<Data>> {
+        let contents = match fs::read_to_string(&self.filename) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        match Self::parse(&contents) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) => {
+                // the cache is overwritten at the end of the poll, keep a copy to look into
+                let backup = format!("{}.corrupt", self.filename);
+                fs::copy(&self.filename, &backup)?;
+                Err(e.context(format!("Unreadable cache, a copy is kept at {}", backup)))
+            }
+        }
     }
 
-    pub fn write(&self, data: &Data) -> Result<()> {
-        let serialized = serde_json::to_string_pretty(data)?;
-        fs::write(&self.filename, serialized)?;
+    fn write(&self, data: &Data) -> Result<()> {
+        let serialized = serde_json::to_string_pretty(&CacheFile {
+            version: FORMAT_VERSION,
+            pull_requests: data,
+        })?;
+
+        // write to a temporary file and move it over the cache, so that a crash in the middle of
+        // the write leaves the previous cache intact
+        let temp_filename = format!("{}.tmp", self.filename);
+        let mut file = fs::File::create(&temp_filename)?;
+        file.write_all(serialized.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_filename, &self.filename)?;
+
         Ok(())
     }
+
+    fn write_pull_request(&self, id: usize, pull_request: &PullRequest) -> Result<()> {
+        let mut data = self.read()?.unwrap_or_default();
+        data.insert(id, pull_request.clone());
+        self.write(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::temp_path;
+
+    #[test]
+    fn contents_are_read_from_lists_of_ids() {
+        let pull_request: PullRequest =
+            serde_json::from_value(json!({ "reviews": [1, 2], "comments": [3] })).unwrap();
+
+        assert_eq!(pull_request.reviews.len(), 2);
+        assert!(pull_request.reviews[&1].is_none());
+        assert!(pull_request.reviews[&2].is_none());
+        assert!(pull_request.comments[&3].is_none());
+        // fields added later are not tracked yet
+        assert!(pull_request.replies.is_none());
+        assert!(pull_request.state.is_none());
+    }
+
+    #[test]
+    fn contents_are_read_from_maps() {
+        let pull_request: PullRequest = serde_json::from_value(json!({
+            "reviews": {},
+            "comments": {
+                "3": { "author": "reviewer", "body": "Looks good" },
+                "4": null,
+            },
+        }))
+        .unwrap();
+
+        let content = pull_request.comments[&3].as_ref().unwrap();
+        assert_eq!(content.author, "reviewer");
+        assert_eq!(content.body, "Looks good");
+        assert!(pull_request.comments[&4].is_none());
+        assert!(pull_request.reviews.is_empty());
+    }
+
+    #[test]
+    fn contents_round_trip() {
+        let pull_request: PullRequest = serde_json::from_value(json!({
+            "reviews": [],
+            "comments": { "3": { "author": "reviewer", "body": "Looks good" } },
+        }))
+        .unwrap();
+
+        let reread: PullRequest =
+            serde_json::from_value(serde_json::to_value(&pull_request).unwrap()).unwrap();
+        assert_eq!(reread.comments[&3].as_ref().unwrap().body, "Looks good");
+    }
+
+    #[test]
+    fn contents_with_invalid_ids_are_rejected() {
+        let result = serde_json::from_value::<PullRequest>(json!({
+            "reviews": [],
+            "comments": { "x": null },
+        }));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_reads_the_versioned_format() {
+        let data = CacheClient::parse(
+            &json!({
+                "version": FORMAT_VERSION,
+                "pull_requests": { "7": { "reviews": [], "comments": [1] } },
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(data.len(), 1);
+        assert!(data[&7].comments.contains_key(&1));
+    }
+
+    #[test]
+    fn parse_reads_unversioned_caches_as_version_0() {
+        let data =
+            CacheClient::parse(&json!({ "7": { "reviews": [2], "comments": [] } }).to_string())
+                .unwrap();
+
+        assert!(data[&7].reviews.contains_key(&2));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_versions() {
+        let newer = json!({ "version": FORMAT_VERSION + 1, "pull_requests": {} });
+        let error = CacheClient::parse(&newer.to_string()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("newer than the supported version"));
+
+        let invalid = json!({ "version": "1", "pull_requests": {} });
+        let error = CacheClient::parse(&invalid.to_string()).unwrap_err();
+        assert!(error.to_string().contains("Invalid cache format version"));
+
+        let missing = json!({ "version": FORMAT_VERSION });
+        assert!(CacheClient::parse(&missing.to_string()).is_err());
+    }
+
+    #[test]
+    fn json_store_round_trip() {
+        let path = temp_path("round-trip.json");
+        let store = CacheClient::new(path.clone());
+        assert!(store.read().unwrap().is_none());
+
+        let pull_request: PullRequest =
+            serde_json::from_value(json!({ "reviews": [], "comments": [1] })).unwrap();
+        store.write(&Data::default()).unwrap();
+        store.write_pull_request(7, &pull_request).unwrap();
+        store.write_pull_request(8, &pull_request).unwrap();
+        let data = store.read().unwrap().unwrap();
+        assert_eq!(data.len(), 2);
+
+        store.write(&Data::from([(8, data[&8].clone())])).unwrap();
+
+        let data = store.read().unwrap().unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), vec![&8]);
+        assert!(data[&8].comments.contains_key(&1));
+
+        let contents: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(contents["version"], FORMAT_VERSION);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn json_store_keeps_a_copy_of_unreadable_caches() {
+        let path = temp_path("corrupt.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        let error = CacheClient::new(path.clone()).read().unwrap_err();
+        let backup = format!("{}.corrupt", path);
+        assert!(error.to_string().contains(&backup));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "{ not json");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup).unwrap();
+    }
 }
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use log::{debug, error};
+use tokio::{process::Command, sync::OnceCell};
+use zbus::{proxy, zvariant::Value, Connection};
+
+use crate::event::Event;
+use crate::notifier::Notifier;
+use crate::settings;
+use crate::util::escape_html;
+
+// the action that is invoked when the notification itself is clicked
+const DEFAULT_ACTION_KEY: &str = "default";
+
+// the values of the urgency hint
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+#[proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, &Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    #[zbus(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// The urls of the actions of each notification that is still open, by notification id. The url
+/// of an action is at the index given by its action key.
+type OpenNotifications = Arc<Mutex<HashMap<u32, Vec<String>>>>;
+
+pub struct DesktopClient {
+    proxy: OnceCell<NotificationsProxy<'static>>,
+    open_notifications: OpenNotifications,
+    expire_timeout: i32,
+    review_comments: settings::ReviewComments,
+}
+
+impl DesktopClient {
+    pub fn new(timeout: Option<u32>, review_comments: settings::ReviewComments) -> Self {
+        Self {
+            proxy: OnceCell::default(),
+            open_notifications: OpenNotifications::default(),
+            // -1 lets the notification server decide
+            expire_timeout: timeout.map_or(-1, |x| {
+                i32::try_from(x.saturating_mul(1000)).unwrap_or(i32::MAX)
+            }),
+            review_comments,
+        }
+    }
+
+    /// Returns the proxy to the notification server, connecting to the session bus and starting
+    /// to listen for actions on the first call
+    async fn proxy(&self) -> Result<&NotificationsProxy<'static>> {
+        self.proxy
+            .get_or_try_init(|| async {
+                let connection = Connection::session().await?;
+                let proxy = NotificationsProxy::new(&connection).await?;
+
+                let action_invoked = proxy.receive_action_invoked().await?;
+                let notification_closed = proxy.receive_notification_closed().await?;
+                tokio::spawn(Self::handle_signals(
+                    action_invoked,
+                    notification_closed,
+                    self.open_notifications.clone(),
+                ));
+
+                Ok(proxy)
+            })
+            .await
+    }
+
+    /// Opens the url of an action in the browser when it is invoked, for as long as the process
+    /// is running
+    async fn handle_signals(
+        mut action_invoked: ActionInvokedStream<'static>,
+        mut notification_closed: NotificationClosedStream<'static>,
+        open_notifications: OpenNotifications,
+    ) {
+        loop {
+            tokio::select! {
+                Some(signal) = action_invoked.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    let url = open_notifications
+                        .lock()
+                        .ok()
+                        .and_then(|x| Self::action_url(&x, args.id, &args.action_key));
+
+                    if let Some(url) = url {
+                        debug!("Opening {}", url);
+                        if let Err(e) = Command::new("xdg-open").arg(&url).spawn() {
+                            error!("Could not open {}: {:?}", url, e);
+                        }
+                    }
+                }
+                Some(signal) = notification_closed.next() => {
+                    if let (Ok(args), Ok(mut x)) = (signal.args(), open_notifications.lock()) {
+                        x.remove(&args.id);
+                    }
+                }
+                else => break,
+            }
+        }
+    }
+
+    /// Returns the url of the action with the given key on an open notification. Clicking the
+    /// notification itself opens the first url.
+    fn action_url(
+        open_notifications: &HashMap<u32, Vec<String>>,
+        id: u32,
+        action_key: &str,
+    ) -> Option<String> {
+        let urls = open_notifications.get(&id)?;
+        let index = match action_key {
+            DEFAULT_ACTION_KEY => 0,
+            key => key.parse().ok()?,
+        };
+        urls.get(index).cloned()
+    }
+}
+
+#[async_trait]
+impl Notifier for DesktopClient {
+    fn name(&self) -> &str {
+        "desktop"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let view_actions = event.view_actions();
+
+        let keys: Vec<String> = (0..view_actions.len()).map(|x| x.to_string()).collect();
+        let mut actions = Vec::default();
+        if !view_actions.is_empty() {
+            actions.extend([DEFAULT_ACTION_KEY, "Open"]);
+        }
+        for (key, &(label, _)) in keys.iter().zip(&view_actions) {
+            actions.extend([key.as_str(), label]);
+        }
+
+        let urgency = Value::U8(match event.mention {
+            true => URGENCY_CRITICAL,
+            false => URGENCY_NORMAL,
+        });
+        let hints = HashMap::from([("urgency", &urgency)]);
+
+        let id = self
+            .proxy()
+            .await?
+            .notify(
+                "prnotify",
+                0,
+                "",
+                event.title(),
+                &escape_html(&event.message(self.review_comments)),
+                &actions,
+                hints,
+                self.expire_timeout,
+            )
+            .await?;
+
+        if let Ok(mut x) = self.open_notifications.lock() {
+            x.insert(
+                id,
+                view_actions
+                    .iter()
+                    .map(|&(_, url)| url.to_owned())
+                    .collect(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::REVIEW_COMMENTS;
+
+    #[test]
+    fn expire_timeout_is_in_milliseconds() {
+        let expire_timeout = |timeout| DesktopClient::new(timeout, REVIEW_COMMENTS).expire_timeout;
+
+        assert_eq!(expire_timeout(None), -1);
+        assert_eq!(expire_timeout(Some(0)), 0);
+        assert_eq!(expire_timeout(Some(10)), 10_000);
+        // too long to fit, or to even be multiplied, rather than wrapping around to negative
+        assert_eq!(expire_timeout(Some(3_000_000)), i32::MAX);
+        assert_eq!(expire_timeout(Some(u32::MAX)), i32::MAX);
+    }
+
+    #[test]
+    fn action_keys_are_indexes_of_the_urls() {
+        let open_notifications = HashMap::from([(
+            7,
+            vec![
+                "https://github.com/org/repo/pull/42".to_owned(),
+                "https://github.com/org/repo/pull/42#issuecomment-1".to_owned(),
+            ],
+        )]);
+        let action_url = |id, key| DesktopClient::action_url(&open_notifications, id, key);
+
+        assert_eq!(
+            action_url(7, DEFAULT_ACTION_KEY).as_deref(),
+            Some("https://github.com/org/repo/pull/42")
+        );
+        assert_eq!(
+            action_url(7, "1").as_deref(),
+            Some("https://github.com/org/repo/pull/42#issuecomment-1")
+        );
+        assert_eq!(action_url(7, "2"), None);
+        assert_eq!(action_url(7, "open"), None);
+        // closed notifications are forgotten
+        assert_eq!(action_url(8, DEFAULT_ACTION_KEY), None);
+    }
+}
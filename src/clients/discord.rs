@@ -0,0 +1,222 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::event::{Event, EventKind};
+use crate::feedback::{CiStatus, Resolution, ReviewState, Transition};
+use crate::notifier::Notifier;
+use crate::settings;
+
+// Discord rejects embeds with descriptions longer than 4096 characters, and author names longer
+// than 256
+const MAX_DESCRIPTION_LEN: usize = 4096;
+const MAX_AUTHOR_NAME_LEN: usize = 256;
+
+const COLOR_BLUE: u32 = 0x0969da;
+const COLOR_GREEN: u32 = 0x1f883d;
+const COLOR_RED: u32 = 0xcf222e;
+const COLOR_GREY: u32 = 0x8c959f;
+const COLOR_LIGHT_BLUE: u32 = 0x54aeff;
+const COLOR_PURPLE: u32 = 0x8250df;
+
+pub struct DiscordClient {
+    client: Client,
+    webhook_url: String,
+    review_comments: settings::ReviewComments,
+}
+
+impl DiscordClient {
+    pub fn try_new(webhook_url: String, review_comments: settings::ReviewComments) -> Result<Self> {
+        let discord_client = Self {
+            client: Client::builder().build()?,
+            webhook_url,
+            review_comments,
+        };
+
+        Ok(discord_client)
+    }
+
+    fn color(event: &Event) -> u32 {
+        match &event.kind {
+            EventKind::NewPullRequest => COLOR_BLUE,
+            EventKind::Comment(_) => COLOR_GREY,
+            EventKind::Review(review) => match review.state() {
+                ReviewState::Approved => COLOR_GREEN,
+                ReviewState::ChangesRequested => COLOR_RED,
+                ReviewState::Commented => COLOR_GREY,
+            },
+            EventKind::Reply(_) => COLOR_GREY,
+            EventKind::Thread(thread) => match thread.resolution() {
+                Resolution::Resolved => COLOR_GREEN,
+                Resolution::Unresolved => COLOR_RED,
+            },
+            EventKind::Push(_) => COLOR_LIGHT_BLUE,
+            EventKind::StateChange(state_change) => match state_change.transition() {
+                Transition::Merged => COLOR_PURPLE,
+                Transition::Closed => COLOR_RED,
+                Transition::Reopened | Transition::ReadyForReview => COLOR_GREEN,
+                Transition::ConvertedToDraft => COLOR_GREY,
+            },
+            EventKind::Ci(ci) => match ci.status() {
+                CiStatus::Pending => COLOR_GREY,
+                CiStatus::Success => COLOR_GREEN,
+                CiStatus::Failure => COLOR_RED,
+            },
+            EventKind::Assignment(_) => COLOR_BLUE,
+            EventKind::Edit(_) => COLOR_GREY,
+            EventKind::Deletion(_) => COLOR_GREY,
+        }
+    }
+
+    fn description(&self, event: &Event) -> String {
+        let links = event
+            .view_actions()
+            .iter()
+            .map(|&(label, url)| format!("[{}]({})", label, url))
+            .collect::<Vec<String>>()
+            .join(" | ");
+
+        // keep the links, and the blank line before them, even if the details have to be truncated
+        let max_details_len = MAX_DESCRIPTION_LEN.saturating_sub(links.chars().count() + 2);
+        match event.details(self.review_comments) {
+            // no room left next to the links for even the ellipsis
+            Some(_) if max_details_len == 0 => links,
+            Some(details) => format!("{}\n\n{}", truncate(&details, max_details_len), links),
+            None => links,
+        }
+    }
+
+    fn embed(&self, event: &Event) -> Value {
+        let mut embed = json!({
+            "author": { "name": truncate(&event.headline(), MAX_AUTHOR_NAME_LEN) },
+            "title": event.pull_request.title,
+            "url": event.pull_request.url,
+            "description": self.description(event),
+            "color": Self::color(event),
+        });
+        if event.mention {
+            embed["footer"] = json!({ "text": "You were mentioned" });
+        }
+
+        embed
+    }
+}
+
+/// Returns the text cut down to `max_len` characters, ending with an ellipsis if it was cut
+fn truncate(text: &str, max_len: usize) -> String {
+    if text.chars().count() <= max_len {
+        return text.to_owned();
+    }
+
+    let mut ret: String = text.chars().take(max_len.saturating_sub(1)).collect();
+    ret.push('…');
+    ret
+}
+
+#[async_trait]
+impl Notifier for DiscordClient {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        self.client
+            .post(&self.webhook_url)
+            .json(&json!({
+                "embeds": [self.embed(event)],
+                // never ping anyone from the contents of a comment
+                "allowed_mentions": { "parse": [] },
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feedback::Comment;
+    use crate::testing::{self, REVIEW_COMMENTS};
+
+    fn client() -> DiscordClient {
+        DiscordClient::try_new(
+            "https://discord.com/api/webhooks/1/token".to_owned(),
+            REVIEW_COMMENTS,
+        )
+        .unwrap()
+    }
+
+    /// Returns an event for a comment by `author`, whose link to the comment is `url`
+    fn comment(author: &str, body: &str, url: &str) -> Event {
+        let pull_request = testing::pull_request(42);
+        let comment = Comment::new(
+            author.to_owned(),
+            body.to_owned(),
+            "2023-06-01T00:00:00Z".to_owned(),
+            pull_request.url.clone(),
+            url.to_owned(),
+        );
+        Event::new(pull_request, EventKind::Comment(comment))
+    }
+
+    #[test]
+    fn description_has_the_details_and_the_links() {
+        let event = testing::comment(42, "Looks good");
+
+        assert_eq!(
+            client().description(&event),
+            "Looks good\n\n[Open PR](https://github.com/org/repo/pull/42) | \
+             [Open Comment](https://github.com/org/repo/pull/42#issuecomment-1)"
+        );
+    }
+
+    #[test]
+    fn long_details_are_truncated_but_keep_the_links() {
+        let event = testing::comment(42, &"a".repeat(5000));
+
+        let description = client().description(&event);
+
+        assert_eq!(description.chars().count(), MAX_DESCRIPTION_LEN);
+        assert!(description.contains("a…\n\n[Open PR]"));
+        assert!(description.ends_with("#issuecomment-1)"));
+    }
+
+    #[test]
+    fn links_that_leave_no_room_drop_the_details() {
+        let url = format!("https://github.com/{}", "a".repeat(MAX_DESCRIPTION_LEN));
+        let event = comment("reviewer", "Looks good", &url);
+
+        let description = client().description(&event);
+
+        assert!(description.starts_with("[Open PR]"));
+        assert!(!description.contains("Looks good"));
+    }
+
+    #[test]
+    fn long_headlines_are_truncated() {
+        let event = comment(&"a".repeat(300), "Looks good", "https://github.com");
+
+        let embed = client().embed(&event);
+
+        let name = embed["author"]["name"].as_str().unwrap();
+        assert_eq!(name.chars().count(), MAX_AUTHOR_NAME_LEN);
+        assert!(name.starts_with("@aaa"));
+        assert!(name.ends_with('…'));
+    }
+
+    #[test]
+    fn embed_has_a_footer_for_mentions() {
+        let mut event = testing::comment(42, "@octocat Looks good");
+        assert_eq!(client().embed(&event).get("footer"), None);
+
+        event.mention = true;
+        let embed = client().embed(&event);
+        assert_eq!(embed["footer"]["text"], "You were mentioned");
+        assert_eq!(embed["color"], COLOR_GREY);
+        assert_eq!(embed["title"], "Add a feature");
+    }
+}
@@ -0,0 +1,345 @@
+use std::sync::Mutex;
+
+use crate::event::{Event, EventKind};
+use crate::notifier::Notifier;
+use crate::settings::{self, SmtpSecurity};
+use crate::util::escape_html as escape;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use lettre::{
+    message::{Mailbox, Mailboxes, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+pub struct EmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailboxes,
+    digest: bool,
+    // events waiting to be sent in the digest at the end of the poll
+    pending: Mutex<Vec<Event>>,
+    review_comments: settings::ReviewComments,
+}
+
+impl EmailClient {
+    pub fn try_new(
+        settings: &settings::Email,
+        review_comments: settings::ReviewComments,
+    ) -> Result<Self> {
+        let host = settings.host.as_str();
+        let mut builder = match settings.security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+
+        if let Some(port) = settings.port {
+            builder = builder.port(port);
+        }
+
+        match (&settings.username, &settings.password) {
+            (Some(username), Some(password)) => {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            (None, None) => {}
+            _ => {
+                return Err(anyhow!(
+                    "Both username and password are required for SMTP auth"
+                ))
+            }
+        }
+
+        if settings.to.is_empty() {
+            return Err(anyhow!("At least one email recipient is required"));
+        }
+
+        let email_client = Self {
+            transport: builder.build(),
+            from: settings.from.parse()?,
+            to: settings
+                .to
+                .iter()
+                .map(|x| x.parse())
+                .collect::<Result<Mailboxes, _>>()?,
+            digest: settings.digest,
+            pending: Mutex::default(),
+            review_comments,
+        };
+
+        Ok(email_client)
+    }
+
+    async fn send(&self, subject: &str, plain: String, html: String) -> Result<()> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(subject);
+        for to in self.to.iter() {
+            builder = builder.to(to.clone());
+        }
+
+        let message = builder.multipart(MultiPart::alternative_plain_html(plain, html))?;
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+
+    /// Renders a single event as plain text
+    fn plain_event(&self, event: &Event) -> String {
+        let mut ret = String::default();
+        if event.mention {
+            ret.push_str("[Mention] ");
+        }
+        ret.push_str(&event.message(self.review_comments));
+        for (label, url) in event.view_actions() {
+            ret.push_str(&format!("\n{}: {}", label, url));
+        }
+        ret
+    }
+
+    /// Renders a single event as html
+    fn html_event(&self, event: &Event) -> String {
+        let links = event
+            .view_actions()
+            .iter()
+            .map(|&(label, url)| format!("<a href=\"{}\">{}</a>", escape(url), escape(label)))
+            .collect::<Vec<String>>()
+            .join(" | ");
+
+        format!(
+            "{}<pre style=\"white-space: pre-wrap; font-family: inherit\">{}</pre><p>{}</p>",
+            if event.mention {
+                "<p><strong>You were mentioned</strong></p>"
+            } else {
+                ""
+            },
+            escape(event.message(self.review_comments).trim_end()),
+            links
+        )
+    }
+
+    /// Renders all the given events as plain text and html, grouped by PR in order of their
+    /// first event
+    fn digest(&self, events: &[Event]) -> (String, String) {
+        let mut groups: Vec<(&Event, Vec<&Event>)> = Vec::default();
+        for event in events {
+            match groups
+                .iter_mut()
+                .find(|(first, _)| first.pull_request.id == event.pull_request.id)
+            {
+                Some((_, group)) => group.push(event),
+                None => groups.push((event, vec![event])),
+            }
+        }
+
+        let mut plain = String::default();
+        let mut html = String::from("<html><body>");
+        for (first, group) in groups {
+            let pr = &first.pull_request;
+
+            plain.push_str(&format!(
+                "{}/{}#{}: {}\n{}\n\n",
+                pr.repo_owner, pr.repo_name, pr.number, pr.title, pr.url
+            ));
+            html.push_str(&format!(
+                "<h3><a href=\"{}\">{}/{}#{}: {}</a></h3>",
+                escape(&pr.url),
+                escape(&pr.repo_owner),
+                escape(&pr.repo_name),
+                pr.number,
+                escape(&pr.title)
+            ));
+
+            for event in group {
+                // the PR itself is already the heading, a new PR only needs its message under it
+                if let EventKind::NewPullRequest = event.kind {
+                    plain.push_str(&format!("{}\n\n", event.message(self.review_comments)));
+                    html.push_str(&format!(
+                        "<p>{}</p>",
+                        escape(&event.message(self.review_comments))
+                    ));
+                    continue;
+                }
+
+                plain.push_str(&format!("{}\n\n", self.plain_event(event)));
+                html.push_str(&self.html_event(event));
+            }
+
+            plain.push_str("---\n\n");
+            html.push_str("<hr>");
+        }
+        html.push_str("</body></html>");
+
+        (plain, html)
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailClient {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        if self.digest {
+            self.pending
+                .lock()
+                .map_err(|_| anyhow!("Email digest lock is poisoned"))?
+                .push(event.clone());
+            return Ok(());
+        }
+
+        let html = format!("<html><body>{}</body></html>", self.html_event(event));
+        let subject = match event.mention {
+            true => format!("[Mention] {}", event.title()),
+            false => event.title().to_owned(),
+        };
+        self.send(&subject, self.plain_event(event), html).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let events = std::mem::take(
+            &mut *self
+                .pending
+                .lock()
+                .map_err(|_| anyhow!("Email digest lock is poisoned"))?,
+        );
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let pr_count = {
+            let mut ids: Vec<usize> = events.iter().map(|x| x.pull_request.id).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            ids.len()
+        };
+        let subject = format!(
+            "prnotify: {} new event{} on {} pull request{}",
+            events.len(),
+            if events.len() == 1 { "" } else { "s" },
+            pr_count,
+            if pr_count == 1 { "" } else { "s" },
+        );
+
+        let (plain, html) = self.digest(&events);
+        if let Err(e) = self.send(&subject, plain, html).await {
+            // keep the events around so that they are sent with the next digest instead
+            let mut pending = self
+                .pending
+                .lock()
+                .map_err(|_| anyhow!("Email digest lock is poisoned"))?;
+            let mut newer = std::mem::replace(&mut *pending, events);
+            pending.append(&mut newer);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{comment, email_settings, pull_request, SmtpSink, REVIEW_COMMENTS};
+
+    #[test]
+    fn requires_both_credentials() {
+        let mut settings = email_settings(25);
+        settings.username = Some("prnotify".to_owned());
+        assert!(EmailClient::try_new(&settings, REVIEW_COMMENTS).is_err());
+    }
+
+    #[test]
+    fn requires_a_recipient() {
+        let mut settings = email_settings(25);
+        settings.to.clear();
+        assert!(EmailClient::try_new(&settings, REVIEW_COMMENTS).is_err());
+    }
+
+    // the transport needs a runtime to be built in
+    #[tokio::test]
+    async fn digest_groups_events_by_pull_request() {
+        let client = EmailClient::try_new(&email_settings(25), REVIEW_COMMENTS).unwrap();
+        let events = vec![
+            Event::new(pull_request(2), EventKind::NewPullRequest),
+            comment(1, "first"),
+            comment(2, "second"),
+            comment(1, "third"),
+        ];
+
+        let (plain, html) = client.digest(&events);
+
+        // PRs are listed in the order of their first event, with all their events under them
+        let positions: Vec<usize> = ["org/repo#2", "second", "org/repo#1", "first", "third"]
+            .iter()
+            .map(|x| plain.find(x).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|x| x[0] < x[1]), "{}", plain);
+        assert_eq!(plain.matches("---").count(), 2);
+        assert_eq!(html.matches("<h3>").count(), 2);
+        // the new PR is the heading, its event does not repeat the links
+        assert_eq!(plain.matches("Open PR:").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_digest_is_sent_with_the_next_one() {
+        let sink = SmtpSink::start(1).await;
+        let client = EmailClient::try_new(&email_settings(sink.port), REVIEW_COMMENTS).unwrap();
+
+        client.notify(&comment(1, "first")).await.unwrap();
+        assert!(client.flush().await.is_err());
+        assert_eq!(client.pending.lock().unwrap().len(), 1);
+
+        client.notify(&comment(2, "second")).await.unwrap();
+        client.flush().await.unwrap();
+
+        let messages = sink.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Subject: prnotify: 2 new events on 2 pull requests"));
+        // the events of the failed digest come first
+        let first = messages[0].find("first").unwrap();
+        assert!(first < messages[0].find("second").unwrap());
+        assert!(client.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn digest_is_sent_when_flushed() {
+        let sink = SmtpSink::start(0).await;
+        let client = EmailClient::try_new(&email_settings(sink.port), REVIEW_COMMENTS).unwrap();
+
+        client.notify(&comment(1, "first")).await.unwrap();
+        client.notify(&comment(1, "second")).await.unwrap();
+        assert!(sink.messages().is_empty());
+        client.flush().await.unwrap();
+        // nothing is left to send
+        client.flush().await.unwrap();
+
+        let messages = sink.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("From: prnotify <prnotify@example.com>"));
+        assert!(messages[0].contains("To: octocat@example.com"));
+        assert!(messages[0].contains("Subject: prnotify: 2 new events on 1 pull request"));
+        assert!(messages[0].contains("org/repo#1: Add a feature"));
+        assert!(messages[0].contains("<h3>"));
+    }
+
+    #[tokio::test]
+    async fn events_are_sent_right_away_without_digest() {
+        let sink = SmtpSink::start(0).await;
+        let mut settings = email_settings(sink.port);
+        settings.digest = false;
+        let client = EmailClient::try_new(&settings, REVIEW_COMMENTS).unwrap();
+
+        client.notify(&comment(1, "first")).await.unwrap();
+        let mut event = comment(2, "second");
+        event.mention = true;
+        client.notify(&event).await.unwrap();
+        client.flush().await.unwrap();
+
+        let messages = sink.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("first"));
+        assert!(messages[1].contains("Subject: [Mention] "));
+        assert!(messages[1].contains("second"));
+        assert!(messages[1].contains("You were mentioned"));
+    }
+}
@@ -1,12 +1,26 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
+use log::warn;
 use regex::Regex;
 use reqwest::{
-    header::{HeaderMap, ACCEPT, AUTHORIZATION, COOKIE, USER_AGENT},
-    Client, Proxy,
+    header::{HeaderMap, ACCEPT, AUTHORIZATION, COOKIE, ETAG, IF_NONE_MATCH, LINK, USER_AGENT},
+    Client, Proxy, StatusCode,
 };
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Deserialize)]
+use crate::clients::cache::{Page, Response};
+use crate::clients::rate_limit::{RateLimit, RateLimiter};
+
+const PER_PAGE: usize = 100;
+
+/// Github only returns the first 1000 results of a search
+const SEARCH_RESULT_CAP: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub login: String,
 }
@@ -18,6 +32,7 @@ pub struct Issue {
     pub title: String,
     pub user: User,
     pub html_url: String,
+    pub updated_at: String,
 }
 
 impl Issue {
@@ -48,18 +63,32 @@ impl Issue {
 
 #[derive(Debug, Deserialize)]
 pub struct SearchIssuesResponse {
+    #[serde(default)]
+    pub total_count: usize,
+    // the search timed out before it found every match
+    #[serde(default)]
+    pub incomplete_results: bool,
     pub items: Vec<Issue>,
 }
 
-#[derive(Debug, Deserialize)]
+impl SearchIssuesResponse {
+    /// Returns whether some of the matching PRs are missing from the items, because the search
+    /// timed out or matched more PRs than Github returns
+    pub fn is_truncated(&self) -> bool {
+        self.incomplete_results || self.items.len() < self.total_count
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Comment {
     pub id: usize,
     pub body: String,
     pub user: User,
     pub html_url: String,
+    pub created_at: String,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ReviewState {
     Pending,
@@ -69,81 +98,538 @@ pub enum ReviewState {
     Dismissed,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Review {
     pub id: usize,
     pub body: String,
     pub state: ReviewState,
     pub user: User,
     pub html_url: String,
+    // pending reviews have not been submitted yet
+    pub submitted_at: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ReviewComment {
     pub id: usize,
     pub pull_request_review_id: usize,
+    pub path: String,
+    pub line: Option<usize>,
+    pub original_line: Option<usize>,
+    pub diff_hunk: String,
+    pub in_reply_to_id: Option<usize>,
     pub body: String,
     pub user: User,
+    pub html_url: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Head {
+    pub sha: String,
+}
+
+// the upper case aliases are the values used by the GraphQL API
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PullRequestState {
+    #[serde(alias = "OPEN")]
+    Open,
+    #[serde(alias = "CLOSED", alias = "MERGED")]
+    Closed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Team {
+    pub slug: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub head: Head,
+    #[serde(default)]
+    pub requested_reviewers: Vec<User>,
+    #[serde(default)]
+    pub requested_teams: Vec<Team>,
+    #[serde(default)]
+    pub assignees: Vec<User>,
+    pub state: PullRequestState,
+    #[serde(default)]
+    pub draft: bool,
+    pub merged_at: Option<String>,
+    pub merged_by: Option<User>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitDetails {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Commit {
+    pub sha: String,
+    pub commit: CommitDetails,
+    pub author: Option<User>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusState {
+    #[serde(alias = "ERROR")]
+    Error,
+    #[serde(alias = "FAILURE")]
+    Failure,
+    #[serde(alias = "PENDING", alias = "EXPECTED")]
+    Pending,
+    #[serde(alias = "SUCCESS")]
+    Success,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub state: StatusState,
+    pub context: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CombinedStatus {
+    pub statuses: Vec<Status>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckRunStatus {
+    #[serde(alias = "COMPLETED")]
+    Completed,
+    #[serde(other)]
+    Incomplete,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckRunConclusion {
+    #[serde(alias = "SUCCESS")]
+    Success,
+    #[serde(alias = "NEUTRAL")]
+    Neutral,
+    #[serde(alias = "SKIPPED")]
+    Skipped,
+    #[serde(alias = "STALE")]
+    Stale,
+    // failure, cancelled, timed_out, action_required, etc.
+    #[serde(other)]
+    Failure,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckRun {
+    pub name: String,
+    pub status: CheckRunStatus,
+    pub conclusion: Option<CheckRunConclusion>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckRunsResponse {
+    pub check_runs: Vec<CheckRun>,
+}
+
+/// The responses of the previous poll that requests are made conditional on, along with the
+/// responses of this poll to be cached for the next one. Requests that Github answers with
+/// "304 Not Modified" reuse the previous response and do not count against the rate limit.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    previous: HashMap<String, Response>,
+    next: HashMap<String, Response>,
+}
+
+impl ResponseCache {
+    pub fn new(previous: HashMap<String, Response>) -> Self {
+        Self {
+            previous,
+            next: HashMap::default(),
+        }
+    }
+
+    /// Returns the responses of this poll. Responses of the previous poll that were not requested
+    /// again, e.g. the CI status of an old head, are dropped.
+    pub fn into_responses(self) -> HashMap<String, Response> {
+        self.next
+    }
+}
+
+/// Returns the items of the response, with the items of the new page replacing the items that
+/// have the same id
+fn merge_by_id(items: &mut Vec<Value>, page: Vec<Value>) {
+    for item in page {
+        match items.iter_mut().find(|x| x.get("id") == item.get("id")) {
+            Some(existing) => *existing = item,
+            None => items.push(item),
+        }
+    }
+}
+
+/// The ways that a request to Github can fail
+#[derive(Debug)]
+pub enum GithubError {
+    /// The token is invalid or expired
+    Auth { message: String },
+    /// The token can't access the resource, e.g. a repo of an organization that requires SSO or
+    /// that a fine-grained token was not granted
+    Forbidden { url: String, message: String },
+    /// The resource does not exist, or the token can't see it
+    NotFound { url: String },
+    /// A rate limit is used up, and does not reset soon enough to wait for it
+    RateLimited {
+        resource: String,
+        reset_in: Option<Duration>,
+    },
+    /// Github failed to handle the request, even after retrying
+    Server { status: StatusCode },
+    /// The response is not what it was expected to be
+    Decode {
+        url: String,
+        source: serde_json::Error,
+    },
+    /// The GraphQL query was rejected
+    Query { messages: Vec<String> },
+    /// The request could not be sent, or the response could not be read
+    Request(reqwest::Error),
+    /// Any other unsuccessful response
+    Unexpected { status: StatusCode, message: String },
+}
+
+impl fmt::Display for GithubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auth { message } => write!(f, "Github authentication failed: {}", message),
+            Self::Forbidden { url, message } => {
+                write!(f, "Github denied access to {}: {}", url, message)
+            }
+            Self::NotFound { url } => write!(f, "Github resource not found: {}", url),
+            Self::RateLimited {
+                resource,
+                reset_in: Some(reset_in),
+            } => write!(
+                f,
+                "Github {} rate limit exhausted, resets in {}s",
+                resource,
+                reset_in.as_secs()
+            ),
+            Self::RateLimited {
+                resource,
+                reset_in: None,
+            } => write!(f, "Github {} rate limit exhausted", resource),
+            Self::Server { status } => write!(f, "Github server error ({})", status),
+            Self::Decode { url, source } => {
+                write!(
+                    f,
+                    "Could not decode Github response from {}: {}",
+                    url, source
+                )
+            }
+            Self::Query { messages } => {
+                write!(f, "Github GraphQL query failed: {}", messages.join(", "))
+            }
+            Self::Request(e) => write!(f, "Github request failed: {}", e),
+            Self::Unexpected { status, message } => {
+                write!(f, "Unexpected Github response ({}): {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GithubError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode { source, .. } => Some(source),
+            Self::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GithubError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Request(value)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    message: String,
+}
+
+impl GithubError {
+    /// Returns the response if it was successful, and the error that its status stands for
+    /// otherwise
+    pub async fn check(
+        resource: &str,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, Self> {
+        let status = response.status();
+        if status.is_success() || status == StatusCode::NOT_MODIFIED {
+            return Ok(response);
+        }
+
+        let url = response.url().to_string();
+        let rate_limit = RateLimit::from_headers(response.headers());
+        let rate_limited = status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN
+                && (response.headers().contains_key("retry-after")
+                    || matches!(rate_limit, Some(x) if x.remaining == 0)));
+
+        // error responses say what went wrong in their message
+        let message = response
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_default()
+            .message;
+
+        Err(match status {
+            _ if rate_limited => Self::RateLimited {
+                resource: resource.to_owned(),
+                reset_in: rate_limit.map(|x| x.reset_in()),
+            },
+            StatusCode::UNAUTHORIZED => Self::Auth { message },
+            StatusCode::FORBIDDEN => Self::Forbidden { url, message },
+            StatusCode::NOT_FOUND => Self::NotFound { url },
+            _ if status.is_server_error() => Self::Server { status },
+            _ => Self::Unexpected { status, message },
+        })
+    }
+
+    /// Parses the body of the response as JSON
+    pub async fn decode<T>(response: reqwest::Response) -> Result<T, Self>
+    where
+        T: DeserializeOwned,
+    {
+        let url = response.url().to_string();
+        let bytes = response.bytes().await?;
+        serde_json::from_slice(&bytes).map_err(|source| Self::Decode { url, source })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitResponse {
+    resources: HashMap<String, RateLimit>,
 }
 
 pub struct GithubClient {
     client: Client,
     base_url: String,
+    rate_limiter: RateLimiter,
 }
 
-impl GithubClient {
-    pub fn try_new(
-        access_token: &str,
-        base_url: String,
-        cookie: Option<String>,
-        proxy_url: Option<String>,
-    ) -> Result<Self> {
-        let mut headers = HeaderMap::new();
-        headers.insert(ACCEPT, "application/vnd.github+json".parse()?);
-        headers.insert(AUTHORIZATION, format!("Bearer {}", access_token).parse()?);
-        headers.insert(USER_AGENT, "prnotify".parse()?);
+/// Builds the HTTP client for the Github APIs, which authenticates every request with the access
+/// token and the cookies if there are any. The client is cheap to clone, so the REST and GraphQL
+/// clients share it along with its connections.
+pub fn http_client(
+    access_token: &str,
+    cookie: Option<String>,
+    proxy_url: Option<String>,
+) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, "application/vnd.github+json".parse()?);
+    headers.insert(AUTHORIZATION, format!("Bearer {}", access_token).parse()?);
+    headers.insert(USER_AGENT, "prnotify".parse()?);
+
+    if let Some(unwrapped) = cookie {
+        headers.insert(COOKIE, unwrapped.parse()?);
+    }
 
-        if let Some(unwrapped) = cookie {
-            headers.insert(COOKIE, unwrapped.parse()?);
+    let mut builder = Client::builder();
+    if let Some(url) = proxy_url {
+        builder = builder.proxy(Proxy::https(url)?);
+    }
+    builder = builder.default_headers(headers);
+
+    Ok(builder.build()?)
+}
+
+impl GithubClient {
+    pub fn new(client: Client, base_url: String) -> Self {
+        Self {
+            client,
+            base_url,
+            rate_limiter: RateLimiter::default(),
         }
+    }
+
+    /// Returns a list of pull requests that involves the authenticated user
+    pub async fn pull_requests(&self, query: &str) -> Result<SearchIssuesResponse, GithubError> {
+        let mut request = self
+            .client
+            .get(format!("{}/search/issues", self.base_url))
+            .query(&[("q", query), ("per_page", "100")]);
+        let mut ret: Option<SearchIssuesResponse> = None;
 
-        let mut builder = Client::builder();
-        if let Some(url) = proxy_url {
-            builder = builder.proxy(Proxy::https(url)?);
+        // follow the next page links until every result is fetched, or Github stops returning them
+        loop {
+            let response = self.rate_limiter.send("search", request).await?;
+            let response = GithubError::check("search", response).await?;
+            let next = Self::next_link(response.headers());
+            let mut page: SearchIssuesResponse = GithubError::decode(response).await?;
+
+            let page_len = page.items.len();
+            match &mut ret {
+                Some(ret) => {
+                    ret.items.append(&mut page.items);
+                    ret.incomplete_results |= page.incomplete_results;
+                }
+                None => ret = Some(page),
+            }
+
+            let fetched = ret.as_ref().map_or(0, |x| x.items.len());
+            let total_count = ret.as_ref().map_or(0, |x| x.total_count);
+            match next {
+                Some(next) if page_len > 0 && fetched < total_count.min(SEARCH_RESULT_CAP) => {
+                    request = self.client.get(next);
+                }
+                _ => break,
+            }
         }
-        builder = builder.default_headers(headers);
 
-        let github_client = Self {
-            client: builder.build()?,
-            base_url,
-        };
+        let ret = ret.unwrap_or(SearchIssuesResponse {
+            total_count: 0,
+            incomplete_results: false,
+            items: Vec::default(),
+        });
+        if ret.incomplete_results {
+            warn!(
+                "Github search for {:?} timed out and returned incomplete results, consider \
+                 narrowing the query",
+                query
+            );
+        }
+        if ret.total_count > SEARCH_RESULT_CAP {
+            warn!(
+                "Github search for {:?} matched {} PRs, only the first {} are returned, consider \
+                 narrowing the query",
+                query, ret.total_count, SEARCH_RESULT_CAP
+            );
+        }
 
-        Ok(github_client)
+        Ok(ret)
     }
 
-    /// Returns a list of pull requests that involves the authenticated user
-    pub async fn pull_requests(&self, query: &str) -> Result<SearchIssuesResponse> {
+    /// Fetches the current budget of every rate limit resource. Checking the rate limit does not
+    /// count against it.
+    pub async fn update_rate_limits(&self) -> Result<(), GithubError> {
         let response = self
             .client
-            .get(format!("{}/search/issues", self.base_url))
-            .query(&[("q", query), ("per_page", "100")])
+            .get(format!("{}/rate_limit", self.base_url))
             .send()
             .await?;
+        let response = GithubError::check("core", response).await?;
+
+        let parsed: RateLimitResponse = GithubError::decode(response).await?;
+        for (resource, rate_limit) in parsed.resources {
+            if let Err(e) = self.rate_limiter.set(&resource, rate_limit) {
+                warn!("{:?}", e);
+            }
+        }
 
-        Ok(response.json().await?)
+        Ok(())
     }
 
-    /// Returns a list of issue comments for the given pull request
+    /// Returns the rate limiter of the client, to check and log the remaining budget
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Returns the details of the given pull request
+    pub async fn pull_request(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        pull_request_id: usize,
+        cache: &mut ResponseCache,
+    ) -> Result<PullRequest, GithubError> {
+        self.get(
+            &format!(
+                "/repos/{}/{}/pulls/{}",
+                repo_owner, repo_name, pull_request_id
+            ),
+            cache,
+        )
+        .await
+    }
+
+    /// Returns a list of commits for the given pull request, oldest first
+    pub async fn commits(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        pull_request_id: usize,
+        cache: &mut ResponseCache,
+    ) -> Result<Vec<Commit>, GithubError> {
+        self.get_all(
+            &format!(
+                "/repos/{}/{}/pulls/{}/commits",
+                repo_owner, repo_name, pull_request_id
+            ),
+            false,
+            cache,
+        )
+        .await
+    }
+
+    /// Returns the combined status of the given commit
+    pub async fn combined_status(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        sha: &str,
+        cache: &mut ResponseCache,
+    ) -> Result<CombinedStatus, GithubError> {
+        self.get(
+            &format!(
+                "/repos/{}/{}/commits/{}/status?per_page={}",
+                repo_owner, repo_name, sha, PER_PAGE
+            ),
+            cache,
+        )
+        .await
+    }
+
+    /// Returns the check runs of the given commit
+    pub async fn check_runs(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        sha: &str,
+        cache: &mut ResponseCache,
+    ) -> Result<CheckRunsResponse, GithubError> {
+        self.get(
+            &format!(
+                "/repos/{}/{}/commits/{}/check-runs?per_page={}",
+                repo_owner, repo_name, sha, PER_PAGE
+            ),
+            cache,
+        )
+        .await
+    }
+
+    /// Returns a list of issue comments for the given pull request. If `since` is set, only the
+    /// comments updated since the last poll are fetched and merged into the cached ones, which
+    /// means that deleted comments are not noticed.
     pub async fn issue_comments(
         &self,
         repo_owner: &str,
         repo_name: &str,
         pull_request_id: usize,
-    ) -> Result<Vec<Comment>> {
-        self.get_all(&format!(
-            "/repos/{}/{}/issues/{}/comments",
-            repo_owner, repo_name, pull_request_id
-        ))
+        since: bool,
+        cache: &mut ResponseCache,
+    ) -> Result<Vec<Comment>, GithubError> {
+        self.get_all(
+            &format!(
+                "/repos/{}/{}/issues/{}/comments",
+                repo_owner, repo_name, pull_request_id
+            ),
+            since,
+            cache,
+        )
         .await
     }
 
@@ -153,11 +639,16 @@ impl GithubClient {
         repo_owner: &str,
         repo_name: &str,
         pull_request_id: usize,
-    ) -> Result<Vec<Review>> {
-        self.get_all(&format!(
-            "/repos/{}/{}/pulls/{}/reviews",
-            repo_owner, repo_name, pull_request_id
-        ))
+        cache: &mut ResponseCache,
+    ) -> Result<Vec<Review>, GithubError> {
+        self.get_all(
+            &format!(
+                "/repos/{}/{}/pulls/{}/reviews",
+                repo_owner, repo_name, pull_request_id
+            ),
+            false,
+            cache,
+        )
         .await
     }
 
@@ -167,38 +658,569 @@ impl GithubClient {
         repo_owner: &str,
         repo_name: &str,
         pull_request_id: usize,
-    ) -> Result<Vec<ReviewComment>> {
-        self.get_all(&format!(
-            "/repos/{}/{}/pulls/{}/comments",
-            repo_owner, repo_name, pull_request_id
-        ))
+        cache: &mut ResponseCache,
+    ) -> Result<Vec<ReviewComment>, GithubError> {
+        // deleted review comments don't need to be noticed, so only fetch the updated ones
+        self.get_all(
+            &format!(
+                "/repos/{}/{}/pulls/{}/comments",
+                repo_owner, repo_name, pull_request_id
+            ),
+            true,
+            cache,
+        )
         .await
     }
 
-    async fn get_all<T>(&self, path: &str) -> Result<Vec<T>>
+    /// Returns the object at the given path, reusing the cached object if it did not change
+    async fn get<T>(&self, path: &str, cache: &mut ResponseCache) -> Result<T, GithubError>
     where
-        T: DeserializeOwned,
+        T: DeserializeOwned + Serialize,
     {
-        let mut ret = Vec::default();
-        let mut page = 1_usize;
+        let previous = cache.previous.remove(path);
+        let previous_page = previous.as_ref().and_then(|x| x.pages.first());
 
-        loop {
-            let response = self
+        let mut request = self.client.get(format!("{}{}", self.base_url, path));
+        if let Some(etag) = previous_page.and_then(|x| x.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = self.rate_limiter.send("core", request).await?;
+
+        let mut next = match (response.status(), previous) {
+            (StatusCode::NOT_MODIFIED, Some(previous)) if !previous.items.is_empty() => previous,
+            _ => {
+                let response = GithubError::check("core", response).await?;
+                let etag = Self::etag(&response);
+                Response {
+                    pages: vec![Page { etag, len: 1 }],
+                    items: vec![GithubError::decode(response).await?],
+                    since: None,
+                }
+            }
+        };
+
+        let ret: T = Self::from_value(path, next.items[0].clone())?;
+        next.items = vec![Self::to_value(path, &ret)?];
+        cache.next.insert(path.to_owned(), next);
+        Ok(ret)
+    }
+
+    /// Returns all the pages of the list at the given path. Pages that did not change since the
+    /// last poll are reused from the cache. If `since` is set, only the items updated since the
+    /// last poll are fetched, and they are merged into the cached items by their ids.
+    async fn get_all<T>(
+        &self,
+        path: &str,
+        since: bool,
+        cache: &mut ResponseCache,
+    ) -> Result<Vec<T>, GithubError>
+    where
+        T: DeserializeOwned + Serialize,
+    {
+        let previous = cache.previous.remove(path).unwrap_or_default();
+        let since = since.then_some(previous.since.clone()).flatten();
+
+        // with `since`, the pages only contain the updated items, the rest come from the cache
+        let mut items = match since {
+            Some(_) => previous.items.clone(),
+            None => Vec::default(),
+        };
+        let mut pages = Vec::default();
+        let mut offset = 0;
+
+        for page in 1.. {
+            let previous_page = previous.pages.get(page - 1);
+            // without `since`, a page can only be reused if its items are in the cache
+            let cached_items = match since {
+                Some(_) => Some(&[][..]),
+                None => previous_page.and_then(|x| previous.items.get(offset..offset + x.len)),
+            };
+
+            let mut request = self
                 .client
                 .get(format!("{}{}", self.base_url, path))
-                .query(&[("per_page", 100), ("page", page)])
-                .send()
-                .await?;
+                .query(&[("per_page", PER_PAGE), ("page", page)]);
+            if let Some(since) = &since {
+                request = request.query(&[("since", since)]);
+            }
+            if let (Some(previous_page), Some(_)) = (previous_page, cached_items) {
+                if let Some(etag) = &previous_page.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+            }
+            let response = self.rate_limiter.send("core", request).await?;
+
+            let len = match (response.status(), previous_page, cached_items) {
+                (StatusCode::NOT_MODIFIED, Some(previous_page), Some(cached_items)) => {
+                    items.extend_from_slice(cached_items);
+                    pages.push(previous_page.clone());
+                    previous_page.len
+                }
+                _ => {
+                    let response = GithubError::check("core", response).await?;
+                    let etag = Self::etag(&response);
+                    let parsed: Vec<Value> = GithubError::decode(response).await?;
+                    let len = parsed.len();
+                    match since {
+                        Some(_) => merge_by_id(&mut items, parsed),
+                        None => items.extend(parsed),
+                    }
+                    pages.push(Page { etag, len });
+                    len
+                }
+            };
 
-            let mut parsed: Vec<T> = response.json().await?;
-            if parsed.is_empty() {
+            offset += len;
+            if len < PER_PAGE {
                 break;
             }
-
-            ret.append(&mut parsed);
-            page += 1;
         }
 
+        // the next poll only needs the items updated after the latest one seen. Only the items
+        // fetched in this poll still have their `updated_at`, the cached ones are older anyway.
+        let latest = items
+            .iter()
+            .filter_map(|x| x.get("updated_at").and_then(Value::as_str))
+            .max()
+            .map(str::to_owned);
+
+        let ret = items
+            .into_iter()
+            .map(|x| Self::from_value(path, x))
+            .collect::<Result<Vec<T>, _>>()?;
+
+        cache.next.insert(
+            path.to_owned(),
+            Response {
+                pages,
+                items: ret
+                    .iter()
+                    .map(|x| Self::to_value(path, x))
+                    .collect::<Result<_, _>>()?,
+                since: latest.or(previous.since),
+            },
+        );
+
         Ok(ret)
     }
+
+    /// Parses a cached item
+    fn from_value<T>(path: &str, value: Value) -> Result<T, GithubError>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_value(value).map_err(|source| GithubError::Decode {
+            url: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Returns the fields of the item that are parsed, to be cached instead of the whole response
+    fn to_value<T>(path: &str, item: &T) -> Result<Value, GithubError>
+    where
+        T: Serialize,
+    {
+        serde_json::to_value(item).map_err(|source| GithubError::Decode {
+            url: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Returns the url of the next page from the `Link` header, e.g.
+    /// `<https://api.github.com/search/issues?q=x&page=2>; rel="next", <...>; rel="last"`
+    fn next_link(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get(LINK)?
+            .to_str()
+            .ok()?
+            .split(',')
+            .find_map(|link| {
+                let (url, params) = link.split_once(';')?;
+                params
+                    .split(';')
+                    .any(|x| x.trim() == r#"rel="next""#)
+                    .then(|| url.trim().trim_start_matches('<').trim_end_matches('>'))
+            })
+            .map(str::to_owned)
+    }
+
+    fn etag(response: &reqwest::Response) -> Option<String> {
+        response
+            .headers()
+            .get(ETAG)
+            .and_then(|x| x.to_str().ok())
+            .map(str::to_owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{response, Server};
+
+    fn client(server: &Server) -> GithubClient {
+        GithubClient::new(Client::new(), server.url.clone())
+    }
+
+    fn comment(id: usize, body: &str, updated_at: &str) -> Value {
+        json!({
+            "id": id,
+            "body": body,
+            "user": { "login": "reviewer", "id": 7 },
+            "html_url": format!("https://github.com/org/repo/pull/1#issuecomment-{}", id),
+            "created_at": "2023-06-01T00:00:00Z",
+            "updated_at": updated_at,
+            "reactions": { "total_count": 0 },
+        })
+    }
+
+    #[test]
+    fn merge_by_id_replaces_and_appends() {
+        let mut items = vec![
+            json!({ "id": 1, "body": "a" }),
+            json!({ "id": 2, "body": "b" }),
+        ];
+
+        merge_by_id(
+            &mut items,
+            vec![
+                json!({ "id": 2, "body": "b2" }),
+                json!({ "id": 3, "body": "c" }),
+            ],
+        );
+
+        assert_eq!(
+            items,
+            vec![
+                json!({ "id": 1, "body": "a" }),
+                json!({ "id": 2, "body": "b2" }),
+                json!({ "id": 3, "body": "c" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_all_reuses_pages_that_did_not_change() {
+        let comments = json!([
+            comment(1, "first", "2023-06-01T00:00:00Z"),
+            comment(2, "second", "2023-06-02T00:00:00Z"),
+        ]);
+        let server = Server::start(vec![
+            response(200, &[("etag", "\"page1\"")], &comments.to_string()),
+            response(304, &[], ""),
+        ])
+        .await;
+        let client = client(&server);
+
+        let mut cache = ResponseCache::default();
+        let fetched = client
+            .issue_comments("org", "repo", 1, false, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(fetched.len(), 2);
+
+        let responses = cache.into_responses();
+        let cached = &responses["/repos/org/repo/issues/1/comments"];
+        assert_eq!(cached.pages[0].etag.as_deref(), Some("\"page1\""));
+        assert_eq!(cached.since.as_deref(), Some("2023-06-02T00:00:00Z"));
+        // only the parsed fields are cached
+        assert!(cached.items[0].get("reactions").is_none());
+        assert!(cached.items[0]["user"].get("id").is_none());
+
+        let mut cache = ResponseCache::new(responses);
+        let refetched = client
+            .issue_comments("org", "repo", 1, false, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(refetched.len(), 2);
+        assert_eq!(refetched[1].body, "second");
+
+        let requests = server.requests();
+        assert!(!requests[0].head.contains("if-none-match"));
+        assert!(requests[1].head.contains("if-none-match: \"page1\""));
+        assert_eq!(
+            cache.into_responses()["/repos/org/repo/issues/1/comments"].pages[0]
+                .etag
+                .as_deref(),
+            Some("\"page1\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn get_all_merges_items_updated_since_the_last_poll() {
+        let server = Server::start(vec![
+            response(
+                200,
+                &[("etag", "\"all\"")],
+                &json!([
+                    comment(1, "first", "2023-06-01T00:00:00Z"),
+                    comment(2, "second", "2023-06-02T00:00:00Z"),
+                ])
+                .to_string(),
+            ),
+            response(
+                200,
+                &[("etag", "\"updated\"")],
+                &json!([
+                    comment(2, "second, edited", "2023-06-03T00:00:00Z"),
+                    comment(3, "third", "2023-06-03T00:00:00Z"),
+                ])
+                .to_string(),
+            ),
+        ])
+        .await;
+        let client = client(&server);
+
+        let mut cache = ResponseCache::default();
+        client
+            .issue_comments("org", "repo", 1, true, &mut cache)
+            .await
+            .unwrap();
+
+        let mut cache = ResponseCache::new(cache.into_responses());
+        let merged = client
+            .issue_comments("org", "repo", 1, true, &mut cache)
+            .await
+            .unwrap();
+
+        let bodies: Vec<&str> = merged.iter().map(|x| x.body.as_str()).collect();
+        assert_eq!(bodies, vec!["first", "second, edited", "third"]);
+        assert!(server.requests()[1]
+            .head
+            .contains("since=2023-06-02t00%3a00%3a00z"));
+        assert_eq!(
+            cache.into_responses()["/repos/org/repo/issues/1/comments"]
+                .since
+                .as_deref(),
+            Some("2023-06-03T00:00:00Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn get_reuses_the_object_when_not_modified() {
+        let pull_request = json!({
+            "head": { "sha": "abc123" },
+            "state": "open",
+            "draft": false,
+            "merged_at": null,
+            "merged_by": null,
+            "body": "A long description",
+        });
+        let server = Server::start(vec![
+            response(200, &[("etag", "\"pr\"")], &pull_request.to_string()),
+            response(304, &[], ""),
+        ])
+        .await;
+        let client = client(&server);
+
+        let mut cache = ResponseCache::default();
+        client
+            .pull_request("org", "repo", 1, &mut cache)
+            .await
+            .unwrap();
+        let responses = cache.into_responses();
+        assert!(responses["/repos/org/repo/pulls/1"].items[0]
+            .get("body")
+            .is_none());
+
+        let mut cache = ResponseCache::new(responses);
+        let details = client
+            .pull_request("org", "repo", 1, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(details.head.sha, "abc123");
+        assert!(server.requests()[1].head.contains("if-none-match: \"pr\""));
+    }
+
+    /// Returns what the response with the given status, headers and body is checked to be
+    async fn check(
+        status: u16,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Result<StatusCode, GithubError> {
+        let server = Server::start(vec![response(status, headers, body)]).await;
+        let response = Client::new()
+            .get(format!("{}/repos/org/repo", server.url))
+            .send()
+            .await
+            .unwrap();
+        GithubError::check("core", response)
+            .await
+            .map(|x| x.status())
+    }
+
+    #[tokio::test]
+    async fn check_passes_successful_responses() {
+        assert_eq!(check(200, &[], "{}").await.unwrap(), StatusCode::OK);
+        assert_eq!(check(304, &[], "").await.unwrap(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn check_maps_statuses_to_errors() {
+        let message = r#"{ "message": "Bad credentials" }"#;
+        assert!(matches!(
+            check(401, &[], message).await,
+            Err(GithubError::Auth { message }) if message == "Bad credentials"
+        ));
+
+        let message = r#"{ "message": "Resource protected by organization SAML enforcement" }"#;
+        assert!(matches!(
+            check(403, &[], message).await,
+            Err(GithubError::Forbidden { url, message })
+                if url.ends_with("/repos/org/repo") && message.contains("SAML")
+        ));
+
+        assert!(matches!(
+            check(404, &[], r#"{ "message": "Not Found" }"#).await,
+            Err(GithubError::NotFound { url }) if url.ends_with("/repos/org/repo")
+        ));
+        assert!(matches!(
+            check(502, &[], "").await,
+            Err(GithubError::Server { status }) if status == StatusCode::BAD_GATEWAY
+        ));
+        assert!(matches!(
+            check(422, &[], r#"{ "message": "Validation Failed" }"#).await,
+            Err(GithubError::Unexpected { status, message })
+                if status == StatusCode::UNPROCESSABLE_ENTITY && message == "Validation Failed"
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_tells_rate_limits_from_denied_access() {
+        assert!(matches!(
+            check(429, &[], "").await,
+            Err(GithubError::RateLimited { reset_in: None, .. })
+        ));
+        // secondary rate limits
+        assert!(matches!(
+            check(403, &[("retry-after", "60")], "").await,
+            Err(GithubError::RateLimited { reset_in: None, .. })
+        ));
+
+        let exhausted = [
+            ("x-ratelimit-limit", "5000"),
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "4102444800"),
+        ];
+        assert!(matches!(
+            check(403, &exhausted, "").await,
+            Err(GithubError::RateLimited { resource, reset_in: Some(_) }) if resource == "core"
+        ));
+
+        // a budget that is not used up is not why the request was denied
+        let remaining = [
+            ("x-ratelimit-limit", "5000"),
+            ("x-ratelimit-remaining", "4999"),
+            ("x-ratelimit-reset", "4102444800"),
+        ];
+        assert!(matches!(
+            check(403, &remaining, "").await,
+            Err(GithubError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn next_link_is_read_from_the_link_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(GithubClient::next_link(&headers), None);
+
+        headers.insert(
+            LINK,
+            r#"<https://api.github.com/search/issues?q=x&page=1>; rel="prev", <https://api.github.com/search/issues?q=x&page=3>; rel="next", <https://api.github.com/search/issues?q=x&page=10>; rel="last""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            GithubClient::next_link(&headers).as_deref(),
+            Some("https://api.github.com/search/issues?q=x&page=3")
+        );
+
+        headers.insert(
+            LINK,
+            r#"<https://api.github.com/search/issues?q=x&page=1>; rel="first""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(GithubClient::next_link(&headers), None);
+    }
+
+    /// Returns a page of search results, with the PRs numbered from `first`
+    fn search_page(total_count: usize, first: usize, len: usize) -> String {
+        let items: Vec<Value> = (first..first + len)
+            .map(|x| {
+                json!({
+                    "id": x,
+                    "number": x,
+                    "title": format!("PR {}", x),
+                    "user": { "login": "octocat" },
+                    "html_url": format!("https://github.com/org/repo/pull/{}", x),
+                    "updated_at": "2023-06-01T00:00:00Z",
+                })
+            })
+            .collect();
+        json!({ "total_count": total_count, "incomplete_results": false, "items": items })
+            .to_string()
+    }
+
+    const NEXT: (&str, &str) = ("link", r#"<{url}/search/issues?q=x&page=next>; rel="next""#);
+
+    #[tokio::test]
+    async fn pull_requests_follow_the_next_links() {
+        let server = Server::start(vec![
+            response(200, &[NEXT], &search_page(150, 0, 100)),
+            response(200, &[], &search_page(150, 100, 50)),
+        ])
+        .await;
+
+        let prs = client(&server).pull_requests("is:pr").await.unwrap();
+
+        assert_eq!(prs.items.len(), 150);
+        assert!(!prs.is_truncated());
+        let requests = server.requests();
+        assert!(requests[0].head.contains("q=is%3apr"));
+        assert!(requests[1].head.contains("page=next"));
+    }
+
+    #[tokio::test]
+    async fn pull_requests_stop_at_the_search_cap() {
+        let pages = (0..SEARCH_RESULT_CAP / PER_PAGE)
+            .map(|x| response(200, &[NEXT], &search_page(1500, x * PER_PAGE, PER_PAGE)))
+            .collect();
+        let server = Server::start(pages).await;
+
+        let prs = client(&server).pull_requests("is:pr").await.unwrap();
+
+        assert_eq!(prs.items.len(), SEARCH_RESULT_CAP);
+        assert_eq!(server.requests().len(), SEARCH_RESULT_CAP / PER_PAGE);
+        assert!(prs.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn pull_requests_stop_on_an_empty_page() {
+        let server = Server::start(vec![
+            response(200, &[NEXT], &search_page(300, 0, 100)),
+            response(200, &[NEXT], &search_page(300, 100, 0)),
+        ])
+        .await;
+
+        let prs = client(&server).pull_requests("is:pr").await.unwrap();
+
+        assert_eq!(prs.items.len(), 100);
+        assert_eq!(server.requests().len(), 2);
+        assert!(prs.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn pull_requests_with_incomplete_results_are_truncated() {
+        let mut page: Value = serde_json::from_str(&search_page(1, 0, 1)).unwrap();
+        page["incomplete_results"] = json!(true);
+        let server = Server::start(vec![response(200, &[], &page.to_string())]).await;
+
+        let prs = client(&server).pull_requests("is:pr").await.unwrap();
+
+        assert_eq!(prs.items.len(), 1);
+        assert!(prs.is_truncated());
+    }
 }
@@ -0,0 +1,889 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::warn;
+use reqwest::{header::ACCEPT, Client};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use crate::clients::github::{
+    self, CheckRun, CheckRunsResponse, CombinedStatus, CommitDetails, GithubError, Head,
+    PullRequestState, ReviewState, Status, Team, User,
+};
+use crate::clients::rate_limit::RateLimiter;
+use crate::fetcher::PullRequestData;
+
+/// The number of PRs fetched by a single query. Every PR can return up to about 10000 nodes, and
+/// Github limits a query to 500000 nodes.
+pub const BATCH_SIZE: usize = 20;
+
+const REVIEW_THREADS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $number: Int!, $after: String) {
+  repository(owner: $owner, name: $name) {
+    pullRequest(number: $number) {
+      reviewThreads(first: 100, after: $after) {
+        pageInfo {
+          hasNextPage
+          endCursor
+        }
+        nodes {
+          id
+          isResolved
+          resolvedBy {
+            login
+          }
+          path
+          line
+          originalLine
+          comments(first: 1) {
+            nodes {
+              author {
+                login
+              }
+              body
+              url
+              createdAt
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+const PULL_REQUEST_FRAGMENT: &str = r#"
+fragment PullRequestFields on PullRequest {
+  headRefOid
+  state
+  isDraft
+  mergedAt
+  mergedBy {
+    login
+  }
+  reviewRequests(first: 100) {
+    totalCount
+    nodes {
+      requestedReviewer {
+        __typename
+        ... on User {
+          login
+        }
+        ... on Team {
+          slug
+        }
+      }
+    }
+  }
+  assignees(first: 100) {
+    totalCount
+    nodes {
+      login
+    }
+  }
+  comments(first: 100) {
+    totalCount
+    nodes {
+      databaseId
+      body
+      author {
+        login
+      }
+      url
+      createdAt
+    }
+  }
+  reviews(first: 100) {
+    totalCount
+    nodes {
+      databaseId
+      body
+      state
+      author {
+        login
+      }
+      url
+      submittedAt
+    }
+  }
+  reviewThreads(first: 100) {
+    totalCount
+    nodes {
+      id
+      isResolved
+      resolvedBy {
+        login
+      }
+      path
+      line
+      originalLine
+      comments(first: 100) {
+        totalCount
+        nodes {
+          databaseId
+          body
+          author {
+            login
+          }
+          url
+          createdAt
+          path
+          line
+          originalLine
+          diffHunk
+          replyTo {
+            databaseId
+          }
+          pullRequestReview {
+            databaseId
+          }
+        }
+      }
+    }
+  }
+  commits(last: 100) {
+    totalCount
+    nodes {
+      commit {
+        oid
+        message
+        author {
+          user {
+            login
+          }
+        }
+      }
+    }
+  }
+  headCommit: commits(last: 1) {
+    nodes {
+      commit {
+        statusCheckRollup {
+          contexts(first: 100) {
+            totalCount
+            nodes {
+              __typename
+              ... on StatusContext {
+                context
+                state
+              }
+              ... on CheckRun {
+                name
+                status
+                conclusion
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+#[derive(Debug, Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse<T> {
+    data: Option<T>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadComment {
+    // the author is null for deleted users
+    pub author: Option<User>,
+    pub body: String,
+    pub url: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThreadComments {
+    pub nodes: Vec<ThreadComment>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewThread {
+    pub id: String,
+    pub is_resolved: bool,
+    pub resolved_by: Option<User>,
+    pub path: String,
+    pub line: Option<usize>,
+    pub original_line: Option<usize>,
+    pub comments: ThreadComments,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewThreads {
+    page_info: PageInfo,
+    nodes: Vec<ReviewThread>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewThreadsPullRequest {
+    review_threads: ReviewThreads,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewThreadsRepository {
+    pull_request: Option<ReviewThreadsPullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewThreadsResponse {
+    repository: Option<ReviewThreadsRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection<T> {
+    #[serde(default)]
+    total_count: usize,
+    nodes: Vec<T>,
+}
+
+impl<T> Connection<T> {
+    /// Returns whether there are more nodes than were returned
+    fn is_truncated(&self) -> bool {
+        self.total_count > self.nodes.len()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "__typename")]
+enum RequestedReviewer {
+    User(User),
+    Team(Team),
+    // mannequins and bots
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReviewRequest {
+    requested_reviewer: Option<RequestedReviewer>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseId {
+    database_id: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchComment {
+    database_id: usize,
+    body: String,
+    author: Option<User>,
+    url: String,
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchReview {
+    database_id: usize,
+    body: String,
+    state: ReviewState,
+    author: Option<User>,
+    url: String,
+    submitted_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchReviewComment {
+    database_id: usize,
+    body: String,
+    author: Option<User>,
+    url: String,
+    created_at: String,
+    path: String,
+    line: Option<usize>,
+    original_line: Option<usize>,
+    diff_hunk: String,
+    reply_to: Option<DatabaseId>,
+    pull_request_review: Option<DatabaseId>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchReviewThread {
+    id: String,
+    is_resolved: bool,
+    resolved_by: Option<User>,
+    path: String,
+    line: Option<usize>,
+    original_line: Option<usize>,
+    comments: Connection<BatchReviewComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitAuthor {
+    user: Option<User>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchCommitDetails {
+    oid: String,
+    message: String,
+    author: Option<CommitAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "__typename")]
+enum StatusCheckContext {
+    StatusContext(Status),
+    CheckRun(CheckRun),
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusCheckRollup {
+    contexts: Connection<StatusCheckContext>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeadCommitDetails {
+    status_check_rollup: Option<StatusCheckRollup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitNode<T> {
+    commit: T,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchPullRequest {
+    head_ref_oid: String,
+    state: PullRequestState,
+    is_draft: bool,
+    merged_at: Option<String>,
+    merged_by: Option<User>,
+    review_requests: Connection<ReviewRequest>,
+    assignees: Connection<User>,
+    comments: Connection<BatchComment>,
+    reviews: Connection<BatchReview>,
+    review_threads: Connection<BatchReviewThread>,
+    commits: Connection<CommitNode<BatchCommitDetails>>,
+    head_commit: Connection<CommitNode<HeadCommitDetails>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchRepository {
+    pull_request: Option<BatchPullRequest>,
+}
+
+/// Returns the author of a comment, deleted users are shown as "ghost" like on Github
+fn author_or_ghost(author: Option<User>) -> User {
+    author.unwrap_or_else(|| User {
+        login: "ghost".to_owned(),
+    })
+}
+
+impl BatchPullRequest {
+    /// Returns whether any of the lists of the PR has more items than were returned
+    fn is_truncated(&self) -> bool {
+        self.review_requests.is_truncated()
+            || self.assignees.is_truncated()
+            || self.comments.is_truncated()
+            || self.reviews.is_truncated()
+            || self.review_threads.is_truncated()
+            || self
+                .review_threads
+                .nodes
+                .iter()
+                .any(|x| x.comments.is_truncated())
+            || self.commits.is_truncated()
+            || self
+                .head_commit
+                .nodes
+                .iter()
+                .filter_map(|x| x.commit.status_check_rollup.as_ref())
+                .any(|x| x.contexts.is_truncated())
+    }
+}
+
+impl From<BatchPullRequest> for PullRequestData {
+    fn from(value: BatchPullRequest) -> Self {
+        let mut requested_reviewers = Vec::default();
+        let mut requested_teams = Vec::default();
+        for review_request in value.review_requests.nodes {
+            match review_request.requested_reviewer {
+                Some(RequestedReviewer::User(user)) => requested_reviewers.push(user),
+                Some(RequestedReviewer::Team(team)) => requested_teams.push(team),
+                Some(RequestedReviewer::Other) | None => {}
+            }
+        }
+
+        let details = github::PullRequest {
+            head: Head {
+                sha: value.head_ref_oid,
+            },
+            requested_reviewers,
+            requested_teams,
+            assignees: value.assignees.nodes,
+            state: value.state,
+            draft: value.is_draft,
+            merged_at: value.merged_at,
+            merged_by: value.merged_by,
+        };
+
+        let comments = value
+            .comments
+            .nodes
+            .into_iter()
+            .map(|x| github::Comment {
+                id: x.database_id,
+                body: x.body,
+                user: author_or_ghost(x.author),
+                html_url: x.url,
+                created_at: x.created_at,
+            })
+            .collect();
+
+        let reviews = value
+            .reviews
+            .nodes
+            .into_iter()
+            .map(|x| github::Review {
+                id: x.database_id,
+                body: x.body,
+                state: x.state,
+                user: author_or_ghost(x.author),
+                html_url: x.url,
+                submitted_at: x.submitted_at,
+            })
+            .collect();
+
+        let mut review_comments = Vec::default();
+        let mut review_threads = Vec::default();
+        for thread in value.review_threads.nodes {
+            let first = thread.comments.nodes.first().map(|x| ThreadComment {
+                author: x.author.as_ref().map(|y| User {
+                    login: y.login.clone(),
+                }),
+                body: x.body.clone(),
+                url: x.url.clone(),
+                created_at: x.created_at.clone(),
+            });
+            review_threads.push(ReviewThread {
+                id: thread.id,
+                is_resolved: thread.is_resolved,
+                resolved_by: thread.resolved_by,
+                path: thread.path,
+                line: thread.line,
+                original_line: thread.original_line,
+                comments: ThreadComments {
+                    nodes: first.into_iter().collect(),
+                },
+            });
+
+            for comment in thread.comments.nodes {
+                // comments always belong to a review, but the review can be hidden from the token
+                let Some(review) = comment.pull_request_review else {
+                    continue;
+                };
+                review_comments.push(github::ReviewComment {
+                    id: comment.database_id,
+                    pull_request_review_id: review.database_id,
+                    path: comment.path,
+                    line: comment.line,
+                    original_line: comment.original_line,
+                    diff_hunk: comment.diff_hunk,
+                    in_reply_to_id: comment.reply_to.map(|x| x.database_id),
+                    body: comment.body,
+                    user: author_or_ghost(comment.author),
+                    html_url: comment.url,
+                    created_at: comment.created_at,
+                });
+            }
+        }
+
+        let commits = value
+            .commits
+            .nodes
+            .into_iter()
+            .map(|x| github::Commit {
+                sha: x.commit.oid,
+                commit: CommitDetails {
+                    message: x.commit.message,
+                },
+                author: x.commit.author.and_then(|y| y.user),
+            })
+            .collect();
+
+        let mut combined_status = CombinedStatus {
+            statuses: Vec::default(),
+        };
+        let mut check_runs = CheckRunsResponse {
+            check_runs: Vec::default(),
+        };
+        let contexts = value
+            .head_commit
+            .nodes
+            .into_iter()
+            .filter_map(|x| x.commit.status_check_rollup)
+            .flat_map(|x| x.contexts.nodes);
+        for context in contexts {
+            match context {
+                StatusCheckContext::StatusContext(status) => combined_status.statuses.push(status),
+                StatusCheckContext::CheckRun(check_run) => check_runs.check_runs.push(check_run),
+            }
+        }
+
+        Self {
+            details,
+            comments,
+            reviews,
+            review_comments,
+            commits: Some(commits),
+            combined_status: Some(combined_status),
+            check_runs: Some(check_runs),
+            review_threads: Some(review_threads),
+            responses: HashMap::default(),
+        }
+    }
+}
+
+/// Client for the parts of the Github GraphQL API that have no REST equivalent
+pub struct GraphqlClient {
+    client: Client,
+    url: String,
+    rate_limiter: RateLimiter,
+}
+
+impl GraphqlClient {
+    /// Creates a client that sends its queries with the given HTTP client, see
+    /// `github::http_client`
+    pub fn new(client: Client, url: String) -> Self {
+        Self {
+            client,
+            url,
+            rate_limiter: RateLimiter::default(),
+        }
+    }
+
+    /// Returns the url of the GraphQL endpoint for the given REST API hostname. Github Enterprise
+    /// serves the REST API under `/api/v3` and GraphQL under `/api/graphql`.
+    pub fn url_for(hostname: &str) -> String {
+        match hostname.strip_suffix("/v3") {
+            Some(api) => format!("https://{}/graphql", api),
+            None => format!("https://{}/graphql", hostname),
+        }
+    }
+
+    /// Returns the review threads of the given pull request
+    pub async fn review_threads(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        pull_request_id: usize,
+    ) -> Result<Vec<ReviewThread>, GithubError> {
+        let mut ret = Vec::default();
+        let mut after: Option<String> = None;
+
+        loop {
+            let variables = json!({
+                "owner": repo_owner,
+                "name": repo_name,
+                "number": pull_request_id,
+                "after": after,
+            });
+            let response: ReviewThreadsResponse =
+                self.query(REVIEW_THREADS_QUERY, variables).await?;
+            let mut review_threads = response
+                .repository
+                .and_then(|x| x.pull_request)
+                .ok_or_else(|| GithubError::NotFound {
+                    url: format!("{}/{}#{}", repo_owner, repo_name, pull_request_id),
+                })?
+                .review_threads;
+
+            ret.append(&mut review_threads.nodes);
+            if !review_threads.page_info.has_next_page {
+                break;
+            }
+            after = review_threads.page_info.end_cursor;
+        }
+
+        Ok(ret)
+    }
+
+    /// Returns the data of each of the given pull requests, fetched with a single query. PRs with
+    /// more comments, reviews, threads or commits than fit in the query, or that could not be
+    /// found, are returned as `None`, they need to be fetched with the REST API instead.
+    pub async fn pull_requests(
+        &self,
+        pull_requests: &[(String, String, usize)],
+    ) -> Result<Vec<Option<PullRequestData>>, GithubError> {
+        let mut parameters = Vec::default();
+        let mut fields = Vec::default();
+        let mut variables = serde_json::Map::default();
+        for (i, (repo_owner, repo_name, pull_request_id)) in pull_requests.iter().enumerate() {
+            parameters.push(format!(
+                "$owner{i}: String!, $name{i}: String!, $number{i}: Int!"
+            ));
+            fields.push(format!(
+                "pr{i}: repository(owner: $owner{i}, name: $name{i}) {{ \
+                 pullRequest(number: $number{i}) {{ ...PullRequestFields }} }}"
+            ));
+            variables.insert(format!("owner{}", i), json!(repo_owner));
+            variables.insert(format!("name{}", i), json!(repo_name));
+            variables.insert(format!("number{}", i), json!(pull_request_id));
+        }
+
+        let query = format!(
+            "query({}) {{\n{}\n}}\n{}",
+            parameters.join(", "),
+            fields.join("\n"),
+            PULL_REQUEST_FRAGMENT
+        );
+        let mut response: HashMap<String, Option<BatchRepository>> =
+            self.query(&query, Value::Object(variables)).await?;
+
+        let mut ret = Vec::default();
+        for (i, (repo_owner, repo_name, pull_request_id)) in pull_requests.iter().enumerate() {
+            let pull_request = response
+                .remove(&format!("pr{}", i))
+                .flatten()
+                .and_then(|x| x.pull_request);
+
+            let Some(pull_request) = pull_request else {
+                warn!(
+                    "Pull request {}/{}#{} not found with GraphQL, falling back to REST",
+                    repo_owner, repo_name, pull_request_id
+                );
+                ret.push(None);
+                continue;
+            };
+
+            if pull_request.is_truncated() {
+                warn!(
+                    "Pull request {}/{}#{} is too large for GraphQL, falling back to REST",
+                    repo_owner, repo_name, pull_request_id
+                );
+                ret.push(None);
+            } else {
+                ret.push(Some(pull_request.into()));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Returns the rate limiter of the client, to check and log the remaining budget
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    async fn query<T>(&self, query: &str, variables: Value) -> Result<T, GithubError>
+    where
+        T: DeserializeOwned,
+    {
+        let request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json")
+            .json(&json!({ "query": query, "variables": variables }));
+        let response = self.rate_limiter.send("graphql", request).await?;
+        let response = GithubError::check("graphql", response).await?;
+
+        let parsed: GraphqlResponse<T> = GithubError::decode(response).await?;
+        if !parsed.errors.is_empty() {
+            let messages = parsed.errors.into_iter().map(|x| x.message).collect();
+            return Err(GithubError::Query { messages });
+        }
+
+        parsed.data.ok_or_else(|| GithubError::Query {
+            messages: vec!["no data returned".to_owned()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::github::{CheckRunStatus, StatusState};
+
+    fn connection(nodes: Value) -> Value {
+        let total_count = nodes.as_array().map_or(0, Vec::len);
+        json!({ "totalCount": total_count, "nodes": nodes })
+    }
+
+    fn batch_pull_request() -> Value {
+        json!({
+            "headRefOid": "abc123",
+            "state": "MERGED",
+            "isDraft": false,
+            "mergedAt": "2023-06-02T00:00:00Z",
+            "mergedBy": { "login": "maintainer" },
+            "reviewRequests": connection(json!([
+                { "requestedReviewer": { "__typename": "User", "login": "octocat" } },
+                { "requestedReviewer": { "__typename": "Team", "slug": "core" } },
+                { "requestedReviewer": { "__typename": "Mannequin" } },
+            ])),
+            "assignees": connection(json!([{ "login": "octocat" }])),
+            "comments": connection(json!([{
+                "databaseId": 1,
+                "body": "Nice",
+                "author": null,
+                "url": "https://github.com/org/repo/pull/42#issuecomment-1",
+                "createdAt": "2023-06-01T00:00:00Z",
+            }])),
+            "reviews": connection(json!([{
+                "databaseId": 2,
+                "body": "",
+                "state": "APPROVED",
+                "author": { "login": "reviewer" },
+                "url": "https://github.com/org/repo/pull/42#pullrequestreview-2",
+                "submittedAt": "2023-06-01T01:00:00Z",
+            }])),
+            "reviewThreads": connection(json!([{
+                "id": "thread",
+                "isResolved": true,
+                "resolvedBy": { "login": "octocat" },
+                "path": "src/main.rs",
+                "line": null,
+                "originalLine": 7,
+                "comments": connection(json!([
+                    {
+                        "databaseId": 3,
+                        "body": "Typo",
+                        "author": { "login": "reviewer" },
+                        "url": "https://github.com/org/repo/pull/42#discussion_r3",
+                        "createdAt": "2023-06-01T01:00:00Z",
+                        "path": "src/main.rs",
+                        "line": null,
+                        "originalLine": 7,
+                        "diffHunk": "@@ -1 +1 @@\n-a\n+b",
+                        "replyTo": null,
+                        "pullRequestReview": { "databaseId": 2 },
+                    },
+                    {
+                        "databaseId": 4,
+                        "body": "Fixed",
+                        "author": { "login": "octocat" },
+                        "url": "https://github.com/org/repo/pull/42#discussion_r4",
+                        "createdAt": "2023-06-01T02:00:00Z",
+                        "path": "src/main.rs",
+                        "line": null,
+                        "originalLine": 7,
+                        "diffHunk": "@@ -1 +1 @@\n-a\n+b",
+                        "replyTo": { "databaseId": 3 },
+                        "pullRequestReview": null,
+                    },
+                ])),
+            }])),
+            "commits": connection(json!([{
+                "commit": {
+                    "oid": "abc123",
+                    "message": "Fix the typo",
+                    "author": { "user": null },
+                },
+            }])),
+            "headCommit": {
+                "nodes": [{
+                    "commit": {
+                        "statusCheckRollup": {
+                            "contexts": connection(json!([
+                                { "__typename": "StatusContext", "context": "ci", "state": "SUCCESS" },
+                                {
+                                    "__typename": "CheckRun",
+                                    "name": "build",
+                                    "status": "IN_PROGRESS",
+                                    "conclusion": null,
+                                },
+                            ])),
+                        },
+                    },
+                }],
+            },
+        })
+    }
+
+    #[test]
+    fn url_for_github_and_enterprise() {
+        assert_eq!(
+            GraphqlClient::url_for("api.github.com"),
+            "https://api.github.com/graphql"
+        );
+        assert_eq!(
+            GraphqlClient::url_for("github.example.com/api/v3"),
+            "https://github.example.com/api/graphql"
+        );
+    }
+
+    #[test]
+    fn batch_pull_request_is_converted_to_rest_types() {
+        let batch: BatchPullRequest = serde_json::from_value(batch_pull_request()).unwrap();
+        assert!(!batch.is_truncated());
+
+        let data = PullRequestData::from(batch);
+
+        assert_eq!(data.details.head.sha, "abc123");
+        assert_eq!(data.details.state, PullRequestState::Closed);
+        assert!(data.details.merged_at.is_some());
+        assert_eq!(data.details.requested_reviewers.len(), 1);
+        assert_eq!(data.details.requested_teams[0].slug, "core");
+
+        // deleted users show up as ghost
+        assert_eq!(data.comments[0].user.login, "ghost");
+        assert_eq!(data.reviews[0].state, ReviewState::Approved);
+
+        // the reply without a visible review is skipped, the thread keeps its first comment
+        assert_eq!(data.review_comments.len(), 1);
+        assert_eq!(data.review_comments[0].pull_request_review_id, 2);
+        let review_threads = data.review_threads.unwrap();
+        assert_eq!(review_threads[0].comments.nodes.len(), 1);
+        assert_eq!(review_threads[0].original_line, Some(7));
+
+        let commits = data.commits.unwrap();
+        assert_eq!(commits[0].commit.message, "Fix the typo");
+        assert!(commits[0].author.is_none());
+
+        let statuses = data.combined_status.unwrap().statuses;
+        assert_eq!(statuses[0].state, StatusState::Success);
+        let check_runs = data.check_runs.unwrap().check_runs;
+        assert_eq!(check_runs[0].status, CheckRunStatus::Incomplete);
+    }
+
+    #[test]
+    fn batch_pull_request_with_more_nodes_is_truncated() {
+        let mut value = batch_pull_request();
+        value["comments"]["totalCount"] = json!(101);
+
+        let batch: BatchPullRequest = serde_json::from_value(value).unwrap();
+        assert!(batch.is_truncated());
+    }
+
+    #[test]
+    fn truncated_thread_comments_are_noticed() {
+        let mut value = batch_pull_request();
+        value["reviewThreads"]["nodes"][0]["comments"]["totalCount"] = json!(150);
+
+        let batch: BatchPullRequest = serde_json::from_value(value).unwrap();
+        assert!(batch.is_truncated());
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+
+use crate::event::Event;
+use crate::notifier::Notifier;
+use crate::settings;
+use crate::util::escape_html;
+
+pub struct MatrixClient {
+    client: Client,
+    homeserver_url: Url,
+    access_token: String,
+    room_id: String,
+    // used together with the start time to build unique transaction ids
+    txn_prefix: u128,
+    txn_counter: AtomicU64,
+    review_comments: settings::ReviewComments,
+}
+
+impl MatrixClient {
+    pub fn try_new(
+        homeserver_url: &str,
+        access_token: String,
+        room_id: String,
+        review_comments: settings::ReviewComments,
+    ) -> Result<Self> {
+        let matrix_client = Self {
+            client: Client::builder().build()?,
+            homeserver_url: Url::parse(homeserver_url)?,
+            access_token,
+            room_id,
+            txn_prefix: SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos(),
+            txn_counter: AtomicU64::default(),
+            review_comments,
+        };
+
+        Ok(matrix_client)
+    }
+
+    /// Returns the url to send a message to the room with a new transaction id
+    fn send_url(&self) -> Result<Url> {
+        let txn_id = format!(
+            "prnotify-{}-{}",
+            self.txn_prefix,
+            self.txn_counter.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let mut url = self.homeserver_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid Matrix homeserver url"))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                &txn_id,
+            ]);
+
+        Ok(url)
+    }
+
+    /// Renders the event as html for the formatted body of the message
+    fn formatted_body(&self, event: &Event) -> String {
+        let mut ret = format!(
+            "<strong><a href=\"{}\">{}</a></strong><br><strong>{}</strong>",
+            escape_html(&event.pull_request.url),
+            escape_html(&event.pull_request.title),
+            escape_html(&event.headline()),
+        );
+
+        if event.mention {
+            ret.push_str("<br><em>You were mentioned</em>");
+        }
+
+        // the blockquote is a block of its own, otherwise the links need a line break
+        match event.details(self.review_comments) {
+            Some(details) => ret.push_str(&format!(
+                "<blockquote>{}</blockquote>",
+                escape_html(&details).replace('\n', "<br>")
+            )),
+            None => ret.push_str("<br>"),
+        }
+
+        let links = event
+            .view_actions()
+            .iter()
+            .map(|&(label, url)| {
+                format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape_html(url),
+                    escape_html(label)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" | ");
+        ret.push_str(&links);
+
+        ret
+    }
+}
+
+#[async_trait]
+impl Notifier for MatrixClient {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let mut body = format!(
+            "{}\n\n{}",
+            event.title(),
+            event.message(self.review_comments)
+        );
+        if event.mention {
+            body.push_str("\nYou were mentioned");
+        }
+
+        self.client
+            .put(self.send_url()?)
+            .bearer_auth(&self.access_token)
+            .json(&json!({
+                "msgtype": "m.notice",
+                "body": body,
+                "format": "org.matrix.custom.html",
+                "formatted_body": self.formatted_body(event),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventKind;
+    use crate::testing::{self, REVIEW_COMMENTS};
+
+    fn client() -> MatrixClient {
+        MatrixClient::try_new(
+            "https://matrix.example.com/",
+            "token".to_owned(),
+            "!room:example.com".to_owned(),
+            REVIEW_COMMENTS,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn links_are_on_their_own_line() {
+        let event = Event::new(testing::pull_request(42), EventKind::NewPullRequest);
+
+        assert_eq!(
+            client().formatted_body(&event),
+            "<strong><a href=\"https://github.com/org/repo/pull/42\">Add a feature</a></strong>\
+             <br><strong>@octocat opened Add a feature</strong>\
+             <br><a href=\"https://github.com/org/repo/pull/42\">Open PR</a>"
+        );
+    }
+
+    #[test]
+    fn links_follow_the_mention_and_the_details() {
+        let event = testing::comment(42, "Thanks <3");
+        assert!(client()
+            .formatted_body(&event)
+            .contains("<blockquote>Thanks &lt;3</blockquote><a href="));
+
+        let mut event = Event::new(testing::pull_request(42), EventKind::NewPullRequest);
+        event.mention = true;
+        assert!(client()
+            .formatted_body(&event)
+            .contains("<em>You were mentioned</em><br><a href="));
+    }
+
+    #[test]
+    fn send_urls_have_unique_transaction_ids() {
+        let client = client();
+        let first = client.send_url().unwrap();
+        let second = client.send_url().unwrap();
+
+        assert!(first
+            .as_str()
+            .starts_with("https://matrix.example.com/_matrix/client/v3/rooms/!room:example.com/send/m.room.message/prnotify-"));
+        assert_ne!(first, second);
+    }
+}
@@ -1,3 +1,12 @@
 pub mod cache;
+pub mod desktop;
+pub mod discord;
+pub mod email;
 pub mod github;
+pub mod graphql;
+pub mod matrix;
 pub mod ntfy;
+pub mod rate_limit;
+pub mod slack;
+pub mod sqlite;
+pub mod webhook;
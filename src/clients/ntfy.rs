@@ -1,42 +1,64 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use reqwest::Client;
 
+use crate::event::Event;
+use crate::notifier::Notifier;
+use crate::settings;
+
 pub struct NtfyClient {
     client: Client,
     base_url: String,
     topic: String,
+    review_comments: settings::ReviewComments,
 }
 
 impl NtfyClient {
-    pub fn try_new(base_url: String, topic: String) -> Result<Self> {
+    pub fn try_new(
+        base_url: String,
+        topic: String,
+        review_comments: settings::ReviewComments,
+    ) -> Result<Self> {
         let ntfy_client = Self {
             client: Client::builder().build()?,
             base_url,
             topic,
+            review_comments,
         };
 
         Ok(ntfy_client)
     }
+}
+
+#[async_trait]
+impl Notifier for NtfyClient {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
 
-    pub async fn notify(
-        &self,
-        title: &str,
-        message: String,
-        view_actions: &[(&str, &str)],
-    ) -> Result<()> {
-        let actions_header_value = view_actions
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let actions_header_value = event
+            .view_actions()
             .iter()
             .map(|&(a, b)| format!("view, {}, {};", a, b))
             .collect::<Vec<String>>()
             .join(" ");
 
-        self.client
+        let mut request = self
+            .client
             .post(format!("{}/{}", self.base_url, self.topic))
-            .header("Title", title)
-            .header("Actions", actions_header_value)
-            .body(message)
+            .header("Title", event.title())
+            .header("Actions", actions_header_value);
+
+        if event.mention {
+            request = request.header("Priority", "high").header("Tags", "mention");
+        }
+
+        request
+            .body(event.message(self.review_comments))
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
 
         Ok(())
     }
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use reqwest::{header::HeaderMap, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+
+use crate::clients::github::GithubError;
+
+/// The number of times a request is retried after a server error or a rate limit response
+const MAX_RETRIES: u32 = 3;
+
+/// The delay before the first retry, doubled for every retry after it
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Github asks to wait at least a minute after hitting a secondary rate limit without saying for
+/// how long
+const SECONDARY_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The longest the client sleeps for the rate limit to reset. Requests that would need to wait
+/// longer fail instead, so that a poll does not hang for up to an hour.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// The budget of a Github rate limit resource, e.g. "core", "search" or "graphql"
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    // seconds since the epoch
+    pub reset: u64,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let get = |name: &str| headers.get(name)?.to_str().ok()?.parse().ok();
+        Some(Self {
+            limit: get("x-ratelimit-limit")?,
+            remaining: get("x-ratelimit-remaining")?,
+            reset: get("x-ratelimit-reset")?,
+        })
+    }
+
+    /// Returns how long until the budget is reset
+    pub fn reset_in(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Duration::from_secs(self.reset.saturating_sub(now))
+    }
+}
+
+/// Keeps track of the Github rate limits, and sends requests in a way that respects them
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: Mutex<HashMap<String, RateLimit>>,
+}
+
+impl RateLimiter {
+    /// Returns the last known budget of the given resource
+    pub fn get(&self, resource: &str) -> Result<Option<RateLimit>> {
+        Ok(self
+            .limits
+            .lock()
+            .map_err(|_| anyhow!("Rate limit lock is poisoned"))?
+            .get(resource)
+            .copied())
+    }
+
+    /// Records the budget of the given resource, e.g. from the `/rate_limit` endpoint
+    pub fn set(&self, resource: &str, rate_limit: RateLimit) -> Result<()> {
+        self.limits
+            .lock()
+            .map_err(|_| anyhow!("Rate limit lock is poisoned"))?
+            .insert(resource.to_owned(), rate_limit);
+
+        Ok(())
+    }
+
+    /// Logs the remaining budget of every resource used so far
+    pub fn log(&self) -> Result<()> {
+        let limits = self
+            .limits
+            .lock()
+            .map_err(|_| anyhow!("Rate limit lock is poisoned"))?;
+        let mut resources: Vec<&String> = limits.keys().collect();
+        resources.sort();
+
+        for resource in resources {
+            let rate_limit = &limits[resource];
+            info!(
+                "Github {} rate limit: {}/{} remaining, resets in {}s",
+                resource,
+                rate_limit.remaining,
+                rate_limit.limit,
+                rate_limit.reset_in().as_secs()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Sends the request, counting against the given resource. Waits for the rate limit to reset
+    /// if it is exhausted, and retries with exponential backoff on server errors and rate limit
+    /// responses. The last response is returned if the retries run out, so that the caller can
+    /// report its status.
+    pub async fn send(
+        &self,
+        resource: &str,
+        request: RequestBuilder,
+    ) -> Result<Response, GithubError> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut retries = 0;
+
+        loop {
+            // without the last known budget the request is sent and Github has the final say
+            let known = self.get(resource).unwrap_or_else(|e| {
+                warn!("{:?}", e);
+                None
+            });
+            if let Some(rate_limit) = known.filter(|x| x.remaining == 0) {
+                let wait = rate_limit.reset_in();
+                if wait > MAX_WAIT {
+                    return Err(GithubError::RateLimited {
+                        resource: resource.to_owned(),
+                        reset_in: Some(wait),
+                    });
+                }
+                warn!(
+                    "Github {} rate limit exhausted, waiting {}s for it to reset",
+                    resource,
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+            }
+
+            // requests with streaming bodies can't be retried, they are only sent once
+            let Some(attempt) = request.try_clone() else {
+                return Ok(request.send().await?);
+            };
+            let response = attempt.send().await?;
+
+            let rate_limit = RateLimit::from_headers(response.headers());
+            if let Some(rate_limit) = rate_limit {
+                let resource = response
+                    .headers()
+                    .get("x-ratelimit-resource")
+                    .and_then(|x| x.to_str().ok())
+                    .unwrap_or(resource);
+                if let Err(e) = self.set(resource, rate_limit) {
+                    warn!("{:?}", e);
+                }
+            }
+
+            let retry_after = response
+                .headers()
+                .get("retry-after")
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.parse().ok())
+                .map(Duration::from_secs);
+            let exhausted = rate_limit.filter(|x| x.remaining == 0);
+
+            let status = response.status();
+            let wait = if status == StatusCode::TOO_MANY_REQUESTS
+                || (status == StatusCode::FORBIDDEN
+                    && (retry_after.is_some() || exhausted.is_some()))
+            {
+                match (retry_after, exhausted) {
+                    (Some(retry_after), _) => retry_after,
+                    (None, Some(exhausted)) => exhausted.reset_in(),
+                    (None, None) => SECONDARY_RATE_LIMIT_BACKOFF,
+                }
+            } else if status.is_server_error() {
+                backoff
+            } else {
+                return Ok(response);
+            };
+
+            if retries >= MAX_RETRIES || wait > MAX_WAIT {
+                return Ok(response);
+            }
+
+            warn!(
+                "Github responded with {}, retrying in {}s",
+                status,
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+            retries += 1;
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::{response, Server};
+
+    /// Sends a request to the server, returning its status and how long it took in paused time
+    async fn send(
+        rate_limiter: &RateLimiter,
+        server: &Server,
+    ) -> (Result<StatusCode, GithubError>, Duration) {
+        // idle connections would keep timers around, that paused time skips ahead to
+        let client = Client::builder().pool_max_idle_per_host(0).build().unwrap();
+        let start = Instant::now();
+        let result = rate_limiter
+            .send("core", client.get(format!("{}/x", server.url)))
+            .await
+            .map(|x| x.status());
+        (result, start.elapsed())
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn server_errors_are_retried_with_backoff() {
+        let server = Server::start(vec![
+            response(502, &[], ""),
+            response(503, &[], ""),
+            response(200, &[], ""),
+        ])
+        .await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(elapsed.as_secs(), 1 + 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn last_response_is_returned_when_the_retries_run_out() {
+        let server = Server::start(vec![response(500, &[], ""); 4]).await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.requests().len(), 1 + MAX_RETRIES as usize);
+        assert_eq!(elapsed.as_secs(), 1 + 2 + 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_is_respected() {
+        let server = Server::start(vec![
+            response(429, &[("retry-after", "5")], ""),
+            response(200, &[], ""),
+        ])
+        .await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::OK);
+        assert_eq!(elapsed.as_secs(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn secondary_rate_limits_are_retried() {
+        let server = Server::start(vec![
+            response(403, &[("retry-after", "30")], ""),
+            // without a retry-after, Github asks to wait at least a minute
+            response(429, &[], ""),
+            response(200, &[], ""),
+        ])
+        .await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::OK);
+        assert_eq!(
+            elapsed,
+            Duration::from_secs(30) + SECONDARY_RATE_LIMIT_BACKOFF
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exhausted_rate_limit_responses_wait_for_the_reset() {
+        let reset = (now() + 10).to_string();
+        let server = Server::start(vec![
+            response(
+                403,
+                &[
+                    ("x-ratelimit-limit", "5000"),
+                    ("x-ratelimit-remaining", "0"),
+                    ("x-ratelimit-reset", &reset),
+                ],
+                "",
+            ),
+            response(200, &[], ""),
+        ])
+        .await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 2);
+        // paused time does not move the system clock that the reset is compared to, so the exact
+        // wait can't be told apart from waiting for the recorded budget before the retry
+        assert!(elapsed >= Duration::from_secs(9), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_forbidden_responses_are_not_retried() {
+        let server = Server::start(vec![response(403, &[], "")]).await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::FORBIDDEN);
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(elapsed, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_waits_are_not_retried() {
+        let server = Server::start(vec![response(429, &[("retry-after", "120")], "")]).await;
+
+        let (status, elapsed) = send(&RateLimiter::default(), &server).await;
+
+        assert_eq!(status.unwrap(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(elapsed, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exhausted_budgets_are_waited_on_before_sending() {
+        let server = Server::start(vec![response(200, &[], "")]).await;
+        let rate_limiter = RateLimiter::default();
+        let exhausted = |reset_in| RateLimit {
+            limit: 5000,
+            remaining: 0,
+            reset: now() + reset_in,
+        };
+
+        // the reset is too far away, nothing is sent
+        rate_limiter.set("core", exhausted(3600)).unwrap();
+        let (result, _) = send(&rate_limiter, &server).await;
+        assert!(matches!(
+            result,
+            Err(GithubError::RateLimited {
+                reset_in: Some(_),
+                ..
+            })
+        ));
+        assert!(server.requests().is_empty());
+
+        rate_limiter.set("core", exhausted(5)).unwrap();
+        let (status, elapsed) = send(&rate_limiter, &server).await;
+        assert_eq!(status.unwrap(), StatusCode::OK);
+        assert!((4..=5).contains(&elapsed.as_secs()), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budgets_are_recorded_from_the_response_headers() {
+        let server = Server::start(vec![response(
+            200,
+            &[
+                ("x-ratelimit-limit", "30"),
+                ("x-ratelimit-remaining", "29"),
+                ("x-ratelimit-reset", "1700000000"),
+                ("x-ratelimit-resource", "search"),
+            ],
+            "",
+        )])
+        .await;
+        let rate_limiter = RateLimiter::default();
+
+        send(&rate_limiter, &server).await.0.unwrap();
+
+        let search = rate_limiter.get("search").unwrap().unwrap();
+        assert_eq!((search.limit, search.remaining), (30, 29));
+        assert!(rate_limiter.get("core").unwrap().is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use crate::event::Event;
+use crate::notifier::Notifier;
+use crate::settings;
+
+// Slack rejects section blocks with more than 3000 characters of text
+const MAX_SECTION_TEXT_LEN: usize = 3000;
+
+pub struct SlackClient {
+    client: Client,
+    webhook_url: String,
+    review_comments: settings::ReviewComments,
+}
+
+impl SlackClient {
+    pub fn try_new(webhook_url: String, review_comments: settings::ReviewComments) -> Result<Self> {
+        let slack_client = Self {
+            client: Client::builder().build()?,
+            webhook_url,
+            review_comments,
+        };
+
+        Ok(slack_client)
+    }
+
+    /// Builds the Block Kit message for the given event
+    fn message(&self, event: &Event) -> Value {
+        let mut text = format!("*{}*", escape(&event.headline()));
+        if let Some(details) = event.details(self.review_comments) {
+            text.push_str("\n\n");
+            text.push_str(&escape(&details));
+        }
+
+        let buttons: Vec<Value> = event
+            .view_actions()
+            .iter()
+            .map(|&(label, url)| {
+                json!({
+                    "type": "button",
+                    "text": { "type": "plain_text", "text": label },
+                    "url": url,
+                })
+            })
+            .collect();
+
+        let mut ret = json!({
+            "text": escape(&format!("{}: {}", event.title(), event.headline())),
+            "blocks": [
+                {
+                    "type": "section",
+                    "text": {
+                        "type": "mrkdwn",
+                        "text": format!(
+                            "<{}|{}>",
+                            event.pull_request.url,
+                            escape(&event.pull_request.title)
+                        ),
+                    },
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": truncate(&text, MAX_SECTION_TEXT_LEN) },
+                },
+                {
+                    "type": "actions",
+                    "elements": buttons,
+                },
+            ],
+        });
+
+        if event.mention {
+            let context = json!({
+                "type": "context",
+                "elements": [{ "type": "mrkdwn", "text": ":bell: You were mentioned" }],
+            });
+            if let Some(blocks) = ret["blocks"].as_array_mut() {
+                blocks.insert(1, context);
+            }
+        }
+
+        ret
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackClient {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        self.client
+            .post(&self.webhook_url)
+            .json(&self.message(event))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Escapes the characters that have a special meaning in Slack's mrkdwn format
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn truncate(text: &str, max_len: usize) -> String {
+    if text.chars().count() <= max_len {
+        return text.to_owned();
+    }
+
+    let mut ret: String = text.chars().take(max_len - 1).collect();
+    ret.push('…');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, response, Server, REVIEW_COMMENTS};
+
+    fn client() -> SlackClient {
+        SlackClient::try_new(
+            "https://hooks.slack.com/services/x".to_owned(),
+            REVIEW_COMMENTS,
+        )
+        .unwrap()
+    }
+
+    fn event(body: &str) -> Event {
+        let mut event = testing::comment(42, body);
+        event.pull_request.title = "Fix <script> & stuff".to_owned();
+        event
+    }
+
+    #[test]
+    fn escapes_mrkdwn_control_characters() {
+        assert_eq!(escape("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+    }
+
+    #[test]
+    fn truncates_long_text_on_char_boundaries() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("héllo wörld", 6), "héllo…");
+    }
+
+    #[test]
+    fn message_has_title_details_and_buttons() {
+        let message = client().message(&event("<b>bold</b>"));
+
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            blocks[0]["text"]["text"],
+            "<https://github.com/org/repo/pull/42|Fix &lt;script&gt; &amp; stuff>"
+        );
+        assert_eq!(
+            blocks[1]["text"]["text"],
+            "*@reviewer commented*\n\n&lt;b&gt;bold&lt;/b&gt;"
+        );
+        assert_eq!(blocks[2]["elements"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn mentions_add_a_context_block() {
+        let message = client().message(&event("@octocat ping").with_mention(true));
+
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[1]["type"], "context");
+    }
+
+    #[test]
+    fn long_details_fit_in_a_section() {
+        let message = client().message(&event(&"x".repeat(MAX_SECTION_TEXT_LEN * 2)));
+
+        let text = message["blocks"][1]["text"]["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_SECTION_TEXT_LEN);
+        assert!(text.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn notify_posts_the_message_to_the_webhook() {
+        let server = Server::start(vec![response(200, &[], "ok")]).await;
+        let client =
+            SlackClient::try_new(format!("{}/services/x", server.url), REVIEW_COMMENTS).unwrap();
+        let event = event("Looks good");
+
+        client.notify(&event).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].head.starts_with("post /services/x "));
+        assert!(requests[0].head.contains("content-type: application/json"));
+        let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body, client.message(&event));
+    }
+
+    #[tokio::test]
+    async fn notify_fails_on_error_statuses() {
+        let server = Server::start(vec![
+            response(400, &[], "invalid_blocks"),
+            response(500, &[], ""),
+        ])
+        .await;
+        let client = SlackClient::try_new(server.url.clone(), REVIEW_COMMENTS).unwrap();
+
+        assert!(client.notify(&event("first")).await.is_err());
+        assert!(client.notify(&event("second")).await.is_err());
+        assert_eq!(server.requests().len(), 2);
+    }
+}
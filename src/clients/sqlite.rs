@@ -0,0 +1,557 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use rusqlite::{params, Connection, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::clients::cache::{CacheClient, Content, Contents, Data, PullRequest, Store};
+use crate::event::Event;
+
+/// The migrations of the schema, in order. The `user_version` of the database is the number of
+/// migrations that were applied to it. Never change a migration once released, add a new one.
+const MIGRATIONS: &[&str] = &[r#"
+-- the "initialized" key is set by the first write, until then there is nothing to read
+CREATE TABLE meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+CREATE TABLE pull_requests (
+    id          INTEGER PRIMARY KEY,
+    info        TEXT,
+    state       TEXT,
+    draft       INTEGER NOT NULL,
+    head_sha    TEXT,
+    commits     TEXT NOT NULL,
+    ci          TEXT,
+    assignments TEXT,
+    replies     TEXT,
+    threads     TEXT,
+    responses   TEXT NOT NULL
+);
+
+-- author and body are null for ids imported from caches that only stored the ids
+CREATE TABLE comments (
+    pull_request_id INTEGER NOT NULL REFERENCES pull_requests (id) ON DELETE CASCADE,
+    id              INTEGER NOT NULL,
+    author          TEXT,
+    body            TEXT,
+    PRIMARY KEY (pull_request_id, id)
+);
+
+CREATE TABLE reviews (
+    pull_request_id INTEGER NOT NULL REFERENCES pull_requests (id) ON DELETE CASCADE,
+    id              INTEGER NOT NULL,
+    author          TEXT,
+    body            TEXT,
+    PRIMARY KEY (pull_request_id, id)
+);
+
+-- kept when the PR is dropped, so that the log covers PRs that are no longer watched
+CREATE TABLE deliveries (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    pull_request_id INTEGER NOT NULL,
+    kind            TEXT NOT NULL,
+    headline        TEXT NOT NULL,
+    error           TEXT,
+    sent_at         TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
+
+CREATE INDEX deliveries_pull_request_id ON deliveries (pull_request_id);
+"#];
+
+/// Stores the state of the watched PRs in an SQLite database. Every write happens in a
+/// transaction, so a crash in the middle of a write can't leave the database half written.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database, creating and migrating it if needed. A new database is filled with the
+    /// contents of the JSON cache at `import_path` if there is one.
+    pub fn open(path: &str, import_path: Option<&str>) -> Result<Self> {
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        // the journal mode pragma returns the new mode, which pragma_update does not expect
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+
+        Self::migrate(&mut connection, import_path)?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Applies the migrations that the database is missing. A database that is being created is
+    /// filled with the JSON cache at `import_path` in the same transaction as the first migration,
+    /// so that the import happens exactly once even if it is interrupted.
+    fn migrate(connection: &mut Connection, import_path: Option<&str>) -> Result<()> {
+        let version: usize = connection.pragma_query_value(None, "user_version", |x| x.get(0))?;
+        if version > MIGRATIONS.len() {
+            return Err(anyhow!(
+                "Database version {} is newer than the supported version {}",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+
+        let import = match import_path.filter(|_| version == 0) {
+            Some(import_path) => CacheClient::new(import_path.to_owned())
+                .read()?
+                .map(|data| (import_path, data)),
+            None => None,
+        };
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = connection.transaction()?;
+            tx.execute_batch(migration)?;
+            if let (0, Some((_, data))) = (i, &import) {
+                Self::replace(&tx, data)?;
+            }
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+            info!("Migrated the database to version {}", i + 1);
+        }
+
+        if let Some((import_path, data)) = import {
+            info!("Imported {} PRs from {}", data.len(), import_path);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the stored PRs with the given ones
+    fn replace(tx: &Transaction, data: &Data) -> Result<()> {
+        for (id, pull_request) in data {
+            Self::upsert(tx, *id, pull_request)?;
+        }
+
+        Self::prune(tx, &data.keys().copied().collect())
+    }
+
+    /// Drops the PRs that are no longer watched, along with their comments and reviews
+    fn prune(tx: &Transaction, ids: &HashSet<usize>) -> Result<()> {
+        let stored = tx
+            .prepare("SELECT id FROM pull_requests")?
+            .query_map([], |x| x.get(0))?
+            .collect::<Result<HashSet<usize>, _>>()?;
+        for id in stored.difference(ids) {
+            tx.execute("DELETE FROM pull_requests WHERE id = ?1", params![id])?;
+        }
+
+        Self::initialize(tx)
+    }
+
+    /// Marks the database as holding a state that can be read
+    fn initialize(tx: &Transaction) -> Result<()> {
+        tx.execute(
+            "INSERT OR IGNORE INTO meta (key, value) VALUES ('initialized', '1')",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    fn upsert(tx: &Transaction, id: usize, pull_request: &PullRequest) -> Result<()> {
+        tx.execute(
+            "
+            INSERT INTO pull_requests (
+                id, info, state, draft, head_sha, commits, ci, assignments, replies, threads,
+                responses
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            ON CONFLICT (id) DO UPDATE SET
+                info = excluded.info,
+                state = excluded.state,
+                draft = excluded.draft,
+                head_sha = excluded.head_sha,
+                commits = excluded.commits,
+                ci = excluded.ci,
+                assignments = excluded.assignments,
+                replies = excluded.replies,
+                threads = excluded.threads,
+                responses = excluded.responses
+            ",
+            params![
+                id,
+                to_json(&pull_request.info)?,
+                pull_request
+                    .state
+                    .map(serde_json::to_value)
+                    .transpose()?
+                    .as_ref()
+                    .and_then(Value::as_str),
+                pull_request.draft,
+                pull_request.head_sha,
+                serde_json::to_string(&pull_request.commits)?,
+                to_json(&pull_request.ci)?,
+                to_json(&pull_request.assignments)?,
+                to_json(&pull_request.replies)?,
+                to_json(&pull_request.threads)?,
+                serde_json::to_string(&pull_request.responses)?,
+            ],
+        )?;
+
+        Self::write_contents(tx, "comments", id, &pull_request.comments)?;
+        Self::write_contents(tx, "reviews", id, &pull_request.reviews)?;
+
+        Ok(())
+    }
+
+    fn write_contents(tx: &Transaction, table: &str, id: usize, contents: &Contents) -> Result<()> {
+        tx.execute(
+            &format!("DELETE FROM {} WHERE pull_request_id = ?1", table),
+            params![id],
+        )?;
+
+        let mut statement = tx.prepare(&format!(
+            "INSERT INTO {} (pull_request_id, id, author, body) VALUES (?1, ?2, ?3, ?4)",
+            table
+        ))?;
+        for (k, v) in contents {
+            statement.execute(params![
+                id,
+                k,
+                v.as_ref().map(|x| &x.author),
+                v.as_ref().map(|x| &x.body),
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn read_contents(connection: &Connection, table: &str) -> Result<HashMap<usize, Contents>> {
+        let mut ret: HashMap<usize, Contents> = HashMap::default();
+
+        let mut statement = connection.prepare(&format!(
+            "SELECT pull_request_id, id, author, body FROM {}",
+            table
+        ))?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let author: Option<String> = row.get(2)?;
+            let body: Option<String> = row.get(3)?;
+            let content = match (author, body) {
+                (Some(author), Some(body)) => Some(Content { author, body }),
+                _ => None,
+            };
+            ret.entry(row.get(0)?)
+                .or_default()
+                .insert(row.get(1)?, content);
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Store for SqliteStore {
+    fn read(&self) -> Result<Option<Data>> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?;
+        let initialized: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM meta WHERE key = 'initialized')",
+            [],
+            |x| x.get(0),
+        )?;
+        if !initialized {
+            return Ok(None);
+        }
+
+        let mut comments = Self::read_contents(&connection, "comments")?;
+        let mut reviews = Self::read_contents(&connection, "reviews")?;
+
+        let mut ret = Data::default();
+        let mut statement = connection.prepare(
+            "
+            SELECT id, info, state, draft, head_sha, commits, ci, assignments, replies, threads,
+                   responses
+            FROM   pull_requests
+            ",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: usize = row.get(0)?;
+            let state: Option<String> = row.get(2)?;
+            let commits: String = row.get(5)?;
+            let responses: String = row.get(10)?;
+            let pull_request = PullRequest {
+                reviews: reviews.remove(&id).unwrap_or_default(),
+                comments: comments.remove(&id).unwrap_or_default(),
+                replies: from_json(row.get(8)?)?,
+                threads: from_json(row.get(9)?)?,
+                info: from_json(row.get(1)?)?,
+                state: state
+                    .map(|x| serde_json::from_value(Value::String(x)))
+                    .transpose()?,
+                draft: row.get(3)?,
+                head_sha: row.get(4)?,
+                commits: serde_json::from_str(&commits)?,
+                ci: from_json(row.get(6)?)?,
+                assignments: from_json(row.get(7)?)?,
+                responses: serde_json::from_str(&responses)?,
+            };
+            ret.insert(id, pull_request);
+        }
+
+        Ok(Some(ret))
+    }
+
+    fn write(&self, data: &Data) -> Result<()> {
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?;
+        let tx = connection.transaction()?;
+        Self::replace(&tx, data)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn write_pull_request(&self, id: usize, pull_request: &PullRequest) -> Result<()> {
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?;
+        let tx = connection.transaction()?;
+        Self::upsert(&tx, id, pull_request)?;
+        Self::initialize(&tx)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn record_delivery(&self, event: &Event, error: Option<&anyhow::Error>) -> Result<()> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?;
+        connection.execute(
+            "
+            INSERT INTO deliveries (pull_request_id, kind, headline, error)
+            VALUES (?1, ?2, ?3, ?4)
+            ",
+            params![
+                event.pull_request.id,
+                event.kind.name(),
+                event.headline(),
+                error.map(|x| format!("{:?}", x)),
+            ],
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Serializes an optional value into a JSON column, `None` is stored as null
+fn to_json<T>(value: &Option<T>) -> Result<Option<String>>
+where
+    T: Serialize,
+{
+    Ok(value.as_ref().map(serde_json::to_string).transpose()?)
+}
+
+/// Deserializes an optional value from a JSON column
+fn from_json<T>(value: Option<String>) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    Ok(value.map(|x| serde_json::from_str(&x)).transpose()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::event::EventKind;
+    use crate::testing::temp_path;
+
+    fn pull_request() -> PullRequest {
+        serde_json::from_value(json!({
+            "reviews": { "5": { "author": "reviewer", "body": "Looks good" } },
+            "comments": { "3": null, "4": { "author": "someone", "body": "Thanks" } },
+            "replies": [6],
+            "threads": { "thread": true },
+            "info": {
+                "id": 1,
+                "number": 2,
+                "title": "Add a feature",
+                "url": "https://github.com/org/repo/pull/2",
+                "repo_owner": "org",
+                "repo_name": "repo",
+                "author": "me",
+            },
+            "state": "closed",
+            "draft": true,
+            "head_sha": "head",
+            "commits": ["base", "head"],
+            "ci": { "sha": "head", "status": "failure" },
+            "assignments": {
+                "requested_reviewers": ["reviewer"],
+                "requested_teams": [],
+                "assignees": ["me"],
+            },
+            "responses": {
+                "/repos/org/repo/pulls/2": {
+                    "pages": [{ "etag": "\"pr\"", "len": 1 }],
+                    "items": [{ "draft": true }],
+                    "since": null,
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn migrations_are_applied_once() {
+        let store = SqliteStore::open(":memory:", None).unwrap();
+        let mut connection = store.connection.lock().unwrap();
+
+        let version: usize = connection
+            .pragma_query_value(None, "user_version", |x| x.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        // reapplying would fail on the existing tables
+        SqliteStore::migrate(&mut connection, None).unwrap();
+
+        connection
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        let error = SqliteStore::migrate(&mut connection, None).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn nothing_is_read_before_the_first_write() {
+        let store = SqliteStore::open(":memory:", None).unwrap();
+        assert!(store.read().unwrap().is_none());
+
+        store.write(&Data::default()).unwrap();
+        assert!(store.read().unwrap().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pull_requests_round_trip() {
+        let store = SqliteStore::open(":memory:", None).unwrap();
+        let data = Data::from([(1, pull_request())]);
+
+        store.write(&data).unwrap();
+
+        let read = store.read().unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&data).unwrap()
+        );
+    }
+
+    #[test]
+    fn pull_requests_are_written_one_at_a_time() {
+        let store = SqliteStore::open(":memory:", None).unwrap();
+
+        store.write_pull_request(1, &pull_request()).unwrap();
+        store.write_pull_request(2, &pull_request()).unwrap();
+        let mut updated = pull_request();
+        updated.comments.remove(&4);
+        store.write_pull_request(1, &updated).unwrap();
+
+        let read = store.read().unwrap().unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[&1].comments.len(), 1);
+        assert_eq!(read[&2].comments.len(), 2);
+
+        store.write(&Data::from([(2, read[&2].clone())])).unwrap();
+
+        let read = store.read().unwrap().unwrap();
+        assert_eq!(read.keys().collect::<Vec<_>>(), vec![&2]);
+        // the comments and reviews of the dropped PR go with it
+        let connection = store.connection.lock().unwrap();
+        let orphans: usize = connection
+            .query_row(
+                "SELECT COUNT(*) FROM comments WHERE pull_request_id = 1",
+                [],
+                |x| x.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+    }
+
+    #[test]
+    fn json_cache_is_imported_once() {
+        let json_path = temp_path("import.json");
+        let database_path = temp_path("import.db");
+        let cache = CacheClient::new(json_path.clone());
+        cache.write(&Data::from([(1, pull_request())])).unwrap();
+
+        let store = SqliteStore::open(&database_path, Some(&json_path)).unwrap();
+        let read = store.read().unwrap().unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[&1].commits, vec!["base", "head"]);
+        drop(store);
+
+        // the JSON cache is only read when the database is created
+        cache.write(&Data::from([(7, pull_request())])).unwrap();
+        let store = SqliteStore::open(&database_path, Some(&json_path)).unwrap();
+        let read = store.read().unwrap().unwrap();
+        assert_eq!(read.keys().collect::<Vec<_>>(), vec![&1]);
+        drop(store);
+
+        for path in [
+            json_path,
+            format!("{}-wal", database_path),
+            format!("{}-shm", database_path),
+            database_path,
+        ] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn deliveries_are_recorded() {
+        let store = SqliteStore::open(":memory:", None).unwrap();
+        let event = Event::new(pull_request().info.unwrap(), EventKind::NewPullRequest);
+
+        store.record_delivery(&event, None).unwrap();
+        store
+            .record_delivery(&event, Some(&anyhow!("No notifier could send the event")))
+            .unwrap();
+
+        let connection = store.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT pull_request_id, kind, error FROM deliveries ORDER BY id")
+            .unwrap();
+        let rows = statement
+            .query_map([], |x| {
+                Ok((
+                    x.get::<_, usize>(0)?,
+                    x.get::<_, String>(1)?,
+                    x.get::<_, Option<String>>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (1, "new_pull_request".to_owned(), None),
+                (
+                    1,
+                    "new_pull_request".to_owned(),
+                    Some("No notifier could send the event".to_owned())
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Client,
+};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::event::{self, Event, EventKind};
+use crate::feedback;
+use crate::feedback::{Assignment, CiStatus, Resolution, ReviewState, Subject, Transition};
+use crate::notifier::Notifier;
+
+/// The version of the JSON document sent to the webhook. This is bumped whenever a field is
+/// removed or changes meaning, adding new fields or event kinds does not change the version.
+const SCHEMA_VERSION: u32 = 1;
+
+const SIGNATURE_HEADER: &str = "X-Prnotify-Signature-256";
+
+#[derive(Debug, Serialize)]
+struct PullRequest<'a> {
+    id: usize,
+    number: usize,
+    title: &'a str,
+    url: &'a str,
+    repo_owner: &'a str,
+    repo_name: &'a str,
+    author: &'a str,
+}
+
+impl<'a> From<&'a event::PullRequest> for PullRequest<'a> {
+    fn from(value: &'a event::PullRequest) -> Self {
+        Self {
+            id: value.id,
+            number: value.number,
+            title: &value.title,
+            url: &value.url,
+            repo_owner: &value.repo_owner,
+            repo_name: &value.repo_name,
+            author: &value.author,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Commit<'a> {
+    sha: &'a str,
+    message: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ReviewComment<'a> {
+    path: &'a str,
+    line: Option<usize>,
+    diff_hunk: &'a str,
+    in_reply_to_id: Option<usize>,
+    body: &'a str,
+}
+
+impl<'a> From<&'a feedback::ReviewComment> for ReviewComment<'a> {
+    fn from(value: &'a feedback::ReviewComment) -> Self {
+        Self {
+            path: &value.path,
+            line: value.line,
+            diff_hunk: &value.diff_hunk,
+            in_reply_to_id: value.in_reply_to_id,
+            body: &value.body,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Parent<'a> {
+    author: &'a str,
+    body: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Thread<'a> {
+    resolution: Resolution,
+    location: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Push<'a> {
+    forced: bool,
+    commits: Vec<Commit<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Ci<'a> {
+    status: CiStatus,
+    failed_checks: &'a [String],
+}
+
+/// The JSON document that is sent to the webhook for every event
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    version: u32,
+    kind: &'static str,
+    pull_request: PullRequest<'a>,
+    author: Option<&'a str>,
+    review_state: Option<&'a ReviewState>,
+    body: Option<&'a str>,
+    comments: Vec<&'a str>,
+    review_comments: Vec<ReviewComment<'a>>,
+    parent: Option<Parent<'a>>,
+    thread: Option<Thread<'a>>,
+    push: Option<Push<'a>>,
+    state_change: Option<Transition>,
+    ci: Option<Ci<'a>>,
+    assignment: Option<&'a Assignment>,
+    subject: Option<Subject>,
+    previous_body: Option<&'a str>,
+    mention: bool,
+    url: &'a str,
+}
+
+impl<'a> From<&'a Event> for Payload<'a> {
+    fn from(value: &'a Event) -> Self {
+        let mut ret = Self {
+            version: SCHEMA_VERSION,
+            kind: value.kind.name(),
+            pull_request: PullRequest::from(&value.pull_request),
+            author: value.author(),
+            review_state: None,
+            body: None,
+            comments: Vec::default(),
+            review_comments: Vec::default(),
+            parent: None,
+            thread: None,
+            push: None,
+            state_change: None,
+            ci: None,
+            assignment: None,
+            subject: None,
+            previous_body: None,
+            mention: value.mention,
+            url: &value.pull_request.url,
+        };
+
+        match &value.kind {
+            EventKind::NewPullRequest => {}
+            EventKind::Comment(comment) => {
+                ret.body = Some(comment.body());
+                ret.url = &comment.url;
+            }
+            EventKind::Review(review) => {
+                ret.review_state = Some(review.state());
+                ret.body = review.body();
+                ret.comments = review.comments().iter().map(|x| x.body.as_str()).collect();
+                ret.review_comments = review.comments().iter().map(ReviewComment::from).collect();
+                ret.url = &review.url;
+            }
+            EventKind::Reply(reply) => {
+                ret.body = Some(&reply.comment().body);
+                ret.review_comments = vec![ReviewComment::from(reply.comment())];
+                ret.parent = reply.parent().map(|x| Parent {
+                    author: x.author(),
+                    body: x.body(),
+                });
+                ret.url = &reply.url;
+            }
+            EventKind::Thread(thread) => {
+                ret.parent = thread.comment().map(|x| Parent {
+                    author: x.author(),
+                    body: x.body(),
+                });
+                ret.thread = Some(Thread {
+                    resolution: thread.resolution(),
+                    location: thread.location(),
+                });
+                ret.url = thread.url();
+            }
+            EventKind::Push(push) => {
+                ret.push = Some(Push {
+                    forced: push.forced(),
+                    commits: push
+                        .commits()
+                        .iter()
+                        .map(|x| Commit {
+                            sha: &x.sha,
+                            message: &x.message,
+                        })
+                        .collect(),
+                });
+                ret.url = &push.url;
+            }
+            EventKind::StateChange(state_change) => {
+                ret.state_change = Some(state_change.transition());
+            }
+            EventKind::Ci(ci) => {
+                ret.ci = Some(Ci {
+                    status: ci.status(),
+                    failed_checks: ci.failed_checks(),
+                });
+                ret.url = &ci.url;
+            }
+            EventKind::Assignment(assignment) => {
+                ret.assignment = Some(assignment);
+            }
+            EventKind::Edit(edit) => {
+                ret.subject = Some(edit.subject());
+                ret.body = Some(edit.new_body());
+                ret.previous_body = Some(edit.old_body());
+                ret.url = &edit.url;
+            }
+            EventKind::Deletion(deletion) => {
+                ret.subject = Some(Subject::Comment);
+                ret.body = Some(deletion.body());
+            }
+        }
+
+        ret
+    }
+}
+
+pub struct WebhookClient {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookClient {
+    pub fn try_new(
+        url: String,
+        secret: Option<String>,
+        headers: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let mut header_map = HeaderMap::new();
+        header_map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (name, value) in headers {
+            header_map.insert(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        }
+
+        let webhook_client = Self {
+            client: Client::builder().default_headers(header_map).build()?,
+            url,
+            secret,
+        };
+
+        Ok(webhook_client)
+    }
+
+    /// Returns the hex encoded HMAC-SHA256 of the body, keyed with the secret
+    fn sign(secret: &str, body: &[u8]) -> Result<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+        mac.update(body);
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookClient {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let body = serde_json::to_vec(&Payload::from(event))?;
+
+        let mut request = self.client.post(&self.url);
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", Self::sign(secret, &body)?),
+            );
+        }
+
+        request.body(body).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::feedback::{Comment, Edit};
+    use crate::testing::pull_request;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // the example from the Github docs on validating webhook deliveries
+        assert_eq!(
+            WebhookClient::sign("It's a Secret to Everybody", b"Hello, World!").unwrap(),
+            "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+        // RFC 4231 test case 2
+        assert_eq!(
+            WebhookClient::sign("Jefe", b"what do ya want for nothing?").unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn comment_payload() {
+        let comment = Comment::new(
+            "reviewer".to_owned(),
+            "Looks good".to_owned(),
+            "2023-06-01T00:00:00Z".to_owned(),
+            "https://github.com/org/repo/pull/42".to_owned(),
+            "https://github.com/org/repo/pull/42#issuecomment-7".to_owned(),
+        );
+        let event = Event::new(pull_request(42), EventKind::Comment(comment)).with_mention(true);
+
+        assert_eq!(
+            serde_json::to_value(Payload::from(&event)).unwrap(),
+            json!({
+                "version": SCHEMA_VERSION,
+                "kind": "comment",
+                "pull_request": {
+                    "id": 42,
+                    "number": 42,
+                    "title": "Add a feature",
+                    "url": "https://github.com/org/repo/pull/42",
+                    "repo_owner": "org",
+                    "repo_name": "repo",
+                    "author": "octocat",
+                },
+                "author": "reviewer",
+                "review_state": null,
+                "body": "Looks good",
+                "comments": [],
+                "review_comments": [],
+                "parent": null,
+                "thread": null,
+                "push": null,
+                "state_change": null,
+                "ci": null,
+                "assignment": null,
+                "subject": null,
+                "previous_body": null,
+                "mention": true,
+                "url": "https://github.com/org/repo/pull/42#issuecomment-7",
+            })
+        );
+    }
+
+    #[test]
+    fn push_payload() {
+        let commits = vec![feedback::Commit {
+            sha: "0123456789abcdef".to_owned(),
+            message: "Fix the build".to_owned(),
+        }];
+        let push = feedback::Push::new(
+            "octocat".to_owned(),
+            commits,
+            true,
+            "https://github.com/org/repo/pull/42".to_owned(),
+        );
+        let payload = serde_json::to_value(Payload::from(&Event::new(
+            pull_request(42),
+            EventKind::Push(push),
+        )))
+        .unwrap();
+
+        assert_eq!(payload["kind"], "push");
+        assert_eq!(
+            payload["push"],
+            json!({
+                "forced": true,
+                "commits": [{ "sha": "0123456789abcdef", "message": "Fix the build" }],
+            })
+        );
+        assert_eq!(
+            payload["url"],
+            "https://github.com/org/repo/pull/42/commits"
+        );
+    }
+
+    #[test]
+    fn edit_payload() {
+        let edit = Edit::new(
+            "reviewer".to_owned(),
+            Subject::Review,
+            "old".to_owned(),
+            "new".to_owned(),
+            "https://github.com/org/repo/pull/42".to_owned(),
+            "https://github.com/org/repo/pull/42#pullrequestreview-3".to_owned(),
+        );
+        let payload = serde_json::to_value(Payload::from(&Event::new(
+            pull_request(42),
+            EventKind::Edit(edit),
+        )))
+        .unwrap();
+
+        assert_eq!(payload["kind"], "edit");
+        assert_eq!(payload["subject"], "review");
+        assert_eq!(payload["body"], "new");
+        assert_eq!(payload["previous_body"], "old");
+    }
+}
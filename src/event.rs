@@ -0,0 +1,213 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::clients::github::Issue;
+use crate::feedback::{
+    Assignment, Ci, Comment, Deletion, Edit, Push, Reply, Review, StateChange, Thread,
+};
+use crate::settings;
+
+/// The pull request that an event happened on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub id: usize,
+    pub number: usize,
+    pub title: String,
+    pub url: String,
+    pub repo_owner: String,
+    pub repo_name: String,
+    pub author: String,
+}
+
+impl TryFrom<&Issue> for PullRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Issue) -> Result<Self> {
+        Ok(Self {
+            id: value.id,
+            number: value.number,
+            title: value.title.clone(),
+            url: value.html_url.clone(),
+            repo_owner: value.repo_owner()?,
+            repo_name: value.repo_name()?,
+            author: value.user.login.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    NewPullRequest,
+    Comment(Comment),
+    Review(Review),
+    Reply(Reply),
+    Thread(Thread),
+    Push(Push),
+    StateChange(StateChange),
+    Ci(Ci),
+    Assignment(Assignment),
+    Edit(Edit),
+    Deletion(Deletion),
+}
+
+/// Something that happened on a pull request that should be sent to the notifiers
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub pull_request: PullRequest,
+    pub kind: EventKind,
+    // whether the user or one of their teams is mentioned in the event
+    pub mention: bool,
+}
+
+impl EventKind {
+    /// Returns the name of the kind of event, e.g. "review". The name is the `kind` of the webhook
+    /// payload, changing it is a breaking change of the schema.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NewPullRequest => "new_pull_request",
+            Self::Comment(_) => "comment",
+            Self::Review(_) => "review",
+            Self::Reply(_) => "reply",
+            Self::Thread(_) => "thread",
+            Self::Push(_) => "push",
+            Self::StateChange(_) => "state_change",
+            Self::Ci(_) => "ci",
+            Self::Assignment(_) => "assignment",
+            Self::Edit(_) => "edit",
+            Self::Deletion(_) => "deletion",
+        }
+    }
+}
+
+impl Event {
+    pub fn new(pull_request: PullRequest, kind: EventKind) -> Self {
+        Self {
+            pull_request,
+            kind,
+            mention: false,
+        }
+    }
+
+    pub fn with_mention(mut self, mention: bool) -> Self {
+        self.mention = mention;
+        self
+    }
+
+    /// Returns the title of the notification
+    pub fn title(&self) -> &str {
+        match &self.kind {
+            EventKind::NewPullRequest => "New Pull Request",
+            _ => &self.pull_request.title,
+        }
+    }
+
+    /// Returns the login of the user that triggered the event, if it is known
+    pub fn author(&self) -> Option<&str> {
+        match &self.kind {
+            EventKind::NewPullRequest => Some(&self.pull_request.author),
+            EventKind::Comment(comment) => Some(comment.author()),
+            EventKind::Review(review) => Some(review.author()),
+            EventKind::Reply(reply) => Some(reply.author()),
+            EventKind::Thread(thread) => thread.author(),
+            EventKind::Push(push) => Some(push.author()),
+            EventKind::StateChange(state_change) => state_change.author(),
+            EventKind::Ci(_) => None,
+            EventKind::Assignment(_) => None,
+            EventKind::Edit(edit) => Some(edit.author()),
+            EventKind::Deletion(deletion) => Some(deletion.author()),
+        }
+    }
+
+    /// Returns a one line summary of the event, e.g. "@user approved"
+    pub fn headline(&self) -> String {
+        match &self.kind {
+            EventKind::NewPullRequest => format!(
+                "@{} opened {}",
+                self.pull_request.author, self.pull_request.title
+            ),
+            EventKind::Comment(comment) => comment.headline(),
+            EventKind::Review(review) => review.headline(),
+            EventKind::Reply(reply) => reply.headline(),
+            EventKind::Thread(thread) => thread.headline(),
+            EventKind::Push(push) => push.headline(),
+            EventKind::StateChange(state_change) => state_change.headline(),
+            EventKind::Ci(ci) => ci.headline(),
+            EventKind::Assignment(assignment) => assignment.headline(),
+            EventKind::Edit(edit) => edit.headline(),
+            EventKind::Deletion(deletion) => deletion.headline(),
+        }
+    }
+
+    /// Returns the details of the event that follow the headline, if there are any. The inline
+    /// comments of reviews are included up to the given limits.
+    pub fn details(&self, review_comments: settings::ReviewComments) -> Option<String> {
+        let ret = match &self.kind {
+            EventKind::NewPullRequest => return None,
+            EventKind::Comment(comment) => comment.body().to_owned(),
+            EventKind::Review(review) => review.paragraphs(review_comments).join("\n\n"),
+            EventKind::Reply(reply) => reply.details(),
+            EventKind::Thread(thread) => return thread.details(),
+            EventKind::Push(push) => push.details(),
+            EventKind::StateChange(_) => return None,
+            EventKind::Ci(ci) => ci.details(),
+            EventKind::Assignment(_) => format!(
+                "@{} opened {}",
+                self.pull_request.author, self.pull_request.title
+            ),
+            EventKind::Edit(edit) => edit.details(),
+            EventKind::Deletion(deletion) => deletion.body().to_owned(),
+        };
+
+        (!ret.is_empty()).then_some(ret)
+    }
+
+    /// Returns the plain text body of the notification. The inline comments of reviews are
+    /// included up to the given limits.
+    pub fn message(&self, review_comments: settings::ReviewComments) -> String {
+        match &self.kind {
+            EventKind::NewPullRequest => self.headline(),
+            EventKind::Comment(comment) => comment.to_string(),
+            EventKind::Review(review) => review.message(review_comments),
+            EventKind::Reply(reply) => reply.to_string(),
+            EventKind::Thread(thread) => thread.to_string(),
+            EventKind::Push(push) => push.to_string(),
+            EventKind::StateChange(state_change) => state_change.to_string(),
+            EventKind::Ci(ci) => ci.to_string(),
+            EventKind::Assignment(assignment) => format!(
+                "{}\n@{} opened {}\n",
+                assignment, self.pull_request.author, self.pull_request.title
+            ),
+            EventKind::Edit(edit) => edit.to_string(),
+            EventKind::Deletion(deletion) => deletion.to_string(),
+        }
+    }
+
+    /// Returns the labels and urls of the links that should be attached to the notification
+    pub fn view_actions(&self) -> Vec<(&str, &str)> {
+        match &self.kind {
+            EventKind::NewPullRequest => vec![("Open PR", &self.pull_request.url)],
+            EventKind::Comment(comment) => {
+                vec![("Open PR", &comment.pr_url), ("Open Comment", &comment.url)]
+            }
+            EventKind::Review(review) => {
+                vec![("Open PR", &review.pr_url), ("Open Comment", &review.url)]
+            }
+            EventKind::Reply(reply) => {
+                vec![("Open PR", &reply.pr_url), ("Open Comment", &reply.url)]
+            }
+            EventKind::Thread(thread) => {
+                vec![("Open PR", &thread.pr_url), ("Open Comment", thread.url())]
+            }
+            EventKind::Push(push) => {
+                vec![("Open PR", &push.pr_url), ("Open Commits", &push.url)]
+            }
+            EventKind::StateChange(_) => vec![("Open PR", &self.pull_request.url)],
+            EventKind::Ci(ci) => vec![("Open PR", &ci.pr_url), ("Open Checks", &ci.url)],
+            EventKind::Assignment(_) => vec![("Open PR", &self.pull_request.url)],
+            EventKind::Edit(edit) => {
+                vec![("Open PR", &edit.pr_url), ("Open Comment", &edit.url)]
+            }
+            EventKind::Deletion(_) => vec![("Open PR", &self.pull_request.url)],
+        }
+    }
+}
@@ -1,35 +1,68 @@
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
 use crate::clients::github;
+use crate::settings;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Comment {
     author: String,
     body: String,
+    created_at: String,
     pub pr_url: String,
     pub url: String,
 }
 
 impl Comment {
-    pub fn new(author: String, body: String, pr_url: String, url: String) -> Self {
+    pub fn new(
+        author: String,
+        body: String,
+        created_at: String,
+        pr_url: String,
+        url: String,
+    ) -> Self {
         Self {
             body,
             author,
+            created_at,
             pr_url,
             url,
         }
     }
 }
 
+impl Comment {
+    /// Returns a one line summary of the comment, e.g. "@user commented"
+    pub fn headline(&self) -> String {
+        format!("@{} commented", self.author)
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Returns when the comment was posted, as an ISO 8601 timestamp
+    pub fn created_at(&self) -> &str {
+        &self.created_at
+    }
+}
+
 impl fmt::Display for Comment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "@{} commented:", self.author)?;
-        writeln!(f, "")?;
+        writeln!(f, "{}:", self.headline())?;
+        writeln!(f)?;
         writeln!(f, "{}", self.body)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ReviewState {
     Commented,
     Approved,
@@ -47,12 +80,58 @@ impl From<github::ReviewState> for ReviewState {
     }
 }
 
-#[derive(Debug)]
+/// An inline comment on the diff of a PR that is part of a review
+#[derive(Debug, Clone)]
+pub struct ReviewComment {
+    pub path: String,
+    pub line: Option<usize>,
+    pub diff_hunk: String,
+    pub in_reply_to_id: Option<usize>,
+    pub body: String,
+    pub created_at: String,
+}
+
+impl From<&github::ReviewComment> for ReviewComment {
+    fn from(value: &github::ReviewComment) -> Self {
+        Self {
+            path: value.path.clone(),
+            // outdated comments no longer have a line in the current diff
+            line: value.line.or(value.original_line),
+            diff_hunk: value.diff_hunk.clone(),
+            in_reply_to_id: value.in_reply_to_id,
+            body: value.body.clone(),
+            created_at: value.created_at.clone(),
+        }
+    }
+}
+
+impl ReviewComment {
+    /// Returns the file and line that the comment is on, e.g. "src/main.rs:42"
+    pub fn location(&self) -> String {
+        match self.line {
+            Some(line) => format!("{}:{}", self.path, line),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Returns up to the last `max_lines` lines of the diff hunk, which end at the commented line
+    pub fn hunk(&self, max_lines: usize) -> Vec<&str> {
+        let lines: Vec<&str> = self
+            .diff_hunk
+            .lines()
+            .filter(|x| !x.starts_with("@@"))
+            .collect();
+        lines[lines.len().saturating_sub(max_lines)..].to_vec()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Review {
     author: String,
     state: ReviewState,
     body: Option<String>,
-    comments: Vec<String>,
+    submitted_at: String,
+    comments: Vec<ReviewComment>,
     pub pr_url: String,
     pub url: String,
 }
@@ -62,6 +141,7 @@ impl Review {
         author: String,
         state: ReviewState,
         body: String,
+        submitted_at: String,
         pr_url: String,
         url: String,
     ) -> Self {
@@ -69,43 +149,723 @@ impl Review {
             author,
             state,
             body: (!body.is_empty()).then_some(body),
+            submitted_at,
             comments: Vec::default(),
             pr_url,
             url,
         }
     }
 
-    pub fn add_comment(&mut self, comment: String) {
+    pub fn add_comment(&mut self, comment: ReviewComment) {
         self.comments.push(comment);
     }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn state(&self) -> &ReviewState {
+        &self.state
+    }
+
+    /// Returns when the review was submitted, as an ISO 8601 timestamp
+    pub fn submitted_at(&self) -> &str {
+        &self.submitted_at
+    }
+
+    pub fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+
+    pub fn comments(&self) -> &[ReviewComment] {
+        &self.comments
+    }
+
+    /// Returns a one line summary of the review, e.g. "@user approved"
+    pub fn headline(&self) -> String {
+        match self.state {
+            ReviewState::Approved => format!("@{} approved", self.author),
+            ReviewState::ChangesRequested => format!("@{} requested changes", self.author),
+            ReviewState::Commented => format!("@{} commented", self.author),
+        }
+    }
+
+    /// Returns the paragraphs that make up the body of the review. Each inline comment is shown
+    /// with its location and the end of its diff hunk, up to the given limits.
+    pub fn paragraphs(&self, limits: settings::ReviewComments) -> Vec<String> {
+        let mut ret = Vec::default();
+
+        if let Some(msg) = &self.body {
+            ret.push(msg.clone());
+        }
+
+        for comment in self.comments.iter().take(limits.max_comments) {
+            let mut lines = vec![format!("{}:", comment.location())];
+            lines.extend(
+                comment
+                    .hunk(limits.max_hunk_lines)
+                    .into_iter()
+                    .map(str::to_owned),
+            );
+            lines.push(comment.body.clone());
+            ret.push(lines.join("\n"));
+        }
+
+        let truncated = self.comments.len().saturating_sub(limits.max_comments);
+        if truncated == 1 {
+            ret.push("(+ 1 more comment)".to_owned());
+        } else if truncated > 1 {
+            ret.push(format!("(+ {} more comments)", truncated));
+        }
+
+        ret
+    }
+
+    /// Returns the plain text body of the notification, with the inline comments up to the given
+    /// limits
+    pub fn message(&self, limits: settings::ReviewComments) -> String {
+        let mut ret = format!("{}:\n", self.headline());
+
+        for paragraph in self.paragraphs(limits) {
+            ret.push_str(&format!("\n{}\n", paragraph));
+        }
+
+        ret
+    }
+}
+
+/// A reply to a thread of inline review comments
+#[derive(Debug, Clone)]
+pub struct Reply {
+    author: String,
+    comment: ReviewComment,
+    parent: Option<Comment>,
+    pub pr_url: String,
+    pub url: String,
 }
 
-impl fmt::Display for Review {
+impl Reply {
+    /// Creates a reply. The parent is the first comment of the thread, if it is still there.
+    pub fn new(
+        author: String,
+        comment: ReviewComment,
+        parent: Option<Comment>,
+        pr_url: String,
+        url: String,
+    ) -> Self {
+        Self {
+            author,
+            comment,
+            parent,
+            pr_url,
+            url,
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn comment(&self) -> &ReviewComment {
+        &self.comment
+    }
+
+    /// Returns when the reply was posted, as an ISO 8601 timestamp
+    pub fn created_at(&self) -> &str {
+        &self.comment.created_at
+    }
+
+    pub fn parent(&self) -> Option<&Comment> {
+        self.parent.as_ref()
+    }
+
+    /// Returns a one line summary of the reply, e.g. "@user replied in thread on src/main.rs:42"
+    pub fn headline(&self) -> String {
+        format!(
+            "@{} replied in thread on {}",
+            self.author,
+            self.comment.location()
+        )
+    }
+
+    /// Returns the parent comment quoted, followed by the body of the reply
+    pub fn details(&self) -> String {
+        match &self.parent {
+            Some(parent) => {
+                let quoted = parent
+                    .body()
+                    .lines()
+                    .map(|x| format!("> {}", x))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                format!(
+                    "@{} wrote:\n{}\n\n{}",
+                    parent.author(),
+                    quoted,
+                    self.comment.body
+                )
+            }
+            None => self.comment.body.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let review_state_msg = match self.state {
-            ReviewState::Approved => format!("@{} approved:", self.author),
-            ReviewState::ChangesRequested => format!("@{} requested changes:", self.author),
-            ReviewState::Commented => format!("@{} commented:", self.author),
+        writeln!(f, "{}:", self.headline())?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.details())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub sha: String,
+    pub message: String,
+}
+
+impl Commit {
+    /// Returns the first line of the commit message
+    pub fn summary(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Push {
+    author: String,
+    commits: Vec<Commit>,
+    forced: bool,
+    pub pr_url: String,
+    pub url: String,
+}
+
+impl Push {
+    pub fn new(author: String, commits: Vec<Commit>, forced: bool, pr_url: String) -> Self {
+        let url = format!("{}/commits", pr_url);
+        Self {
+            author,
+            commits,
+            forced,
+            pr_url,
+            url,
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn commits(&self) -> &[Commit] {
+        &self.commits
+    }
+
+    pub fn forced(&self) -> bool {
+        self.forced
+    }
+
+    /// Returns a one line summary of the push, e.g. "@user pushed 2 commits"
+    pub fn headline(&self) -> String {
+        // a force-push that only removed commits
+        if self.commits.is_empty() {
+            return format!("@{} force-pushed", self.author);
+        }
+
+        format!(
+            "@{} {} {} commit{}",
+            self.author,
+            if self.forced {
+                "force-pushed"
+            } else {
+                "pushed"
+            },
+            self.commits.len(),
+            if self.commits.len() == 1 { "" } else { "s" }
+        )
+    }
+
+    /// Returns the list of commits that were pushed, one per line
+    pub fn details(&self) -> String {
+        self.commits
+            .iter()
+            .map(|x| format!("- {} {}", &x.sha[..x.sha.len().min(7)], x.summary()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for Push {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.commits.is_empty() {
+            return writeln!(f, "{}", self.headline());
+        }
+
+        writeln!(f, "{}:", self.headline())?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.details())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transition {
+    Merged,
+    Closed,
+    Reopened,
+    ConvertedToDraft,
+    ReadyForReview,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    author: Option<String>,
+    transition: Transition,
+}
+
+impl StateChange {
+    pub fn new(author: Option<String>, transition: Transition) -> Self {
+        Self { author, transition }
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn transition(&self) -> Transition {
+        self.transition
+    }
+
+    /// Returns a one line summary of the state change, e.g. "PR merged by @user"
+    pub fn headline(&self) -> String {
+        let msg = match self.transition {
+            Transition::Merged => "PR merged",
+            Transition::Closed => "PR closed",
+            Transition::Reopened => "PR reopened",
+            Transition::ConvertedToDraft => "PR converted to draft",
+            Transition::ReadyForReview => "PR marked ready for review",
         };
-        writeln!(f, "{}", review_state_msg)?;
 
-        if let Some(msg) = &self.body {
-            writeln!(f, "")?;
-            writeln!(f, "{}", msg)?;
-        } else if self.comments.len() == 1 {
-            // display the only comment as the review body
-            writeln!(f, "")?;
-            writeln!(f, "{}", self.comments[0])?;
+        match &self.author {
+            Some(author) => format!("{} by @{}", msg, author),
+            None => msg.to_owned(),
         }
+    }
+}
+
+impl fmt::Display for StateChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.headline())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CiStatus {
+    Pending,
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ci {
+    status: CiStatus,
+    failed_checks: Vec<String>,
+    pub pr_url: String,
+    pub url: String,
+}
 
-        if self.comments.len() > 1 {
-            writeln!(f, "")?;
-            writeln!(f, "(+ {} comments)", self.comments.len())?;
-        } else if self.body.is_some() && self.comments.len() == 1 {
-            writeln!(f, "")?;
-            writeln!(f, "(+ 1 comment)")?;
+impl Ci {
+    pub fn new(status: CiStatus, failed_checks: Vec<String>, pr_url: String) -> Self {
+        let url = format!("{}/checks", pr_url);
+        Self {
+            status,
+            failed_checks,
+            pr_url,
+            url,
+        }
+    }
+
+    pub fn status(&self) -> CiStatus {
+        self.status
+    }
+
+    pub fn failed_checks(&self) -> &[String] {
+        &self.failed_checks
+    }
+
+    /// Returns a one line summary of the CI status, e.g. "CI failed"
+    pub fn headline(&self) -> String {
+        match self.status {
+            CiStatus::Pending => "CI is running".to_owned(),
+            CiStatus::Success => "CI passed".to_owned(),
+            CiStatus::Failure => "CI failed".to_owned(),
+        }
+    }
+
+    /// Returns the list of failed checks, one per line
+    pub fn details(&self) -> String {
+        self.failed_checks
+            .iter()
+            .map(|x| format!("- {}", x))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for Ci {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.headline())?;
+
+        if !self.failed_checks.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{}", self.details())?;
         }
 
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Assignment {
+    ReviewRequested,
+    ReReviewRequested,
+    TeamReviewRequested { team: String },
+    Assigned,
+}
+
+impl Assignment {
+    /// Returns a one line summary of the assignment, e.g. "Review requested from you"
+    pub fn headline(&self) -> String {
+        match self {
+            Self::ReviewRequested => "Review requested from you".to_owned(),
+            Self::ReReviewRequested => "Re-review requested from you".to_owned(),
+            Self::TeamReviewRequested { team } => {
+                format!("Review requested from your team @{}", team)
+            }
+            Self::Assigned => "Assigned to you".to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.headline())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Resolved,
+    Unresolved,
+}
+
+/// A review thread being resolved or unresolved
+#[derive(Debug, Clone)]
+pub struct Thread {
+    author: Option<String>,
+    resolution: Resolution,
+    location: String,
+    // the first comment of the thread
+    comment: Option<Comment>,
+    pub pr_url: String,
+}
+
+impl Thread {
+    pub fn new(
+        author: Option<String>,
+        resolution: Resolution,
+        location: String,
+        comment: Option<Comment>,
+        pr_url: String,
+    ) -> Self {
+        Self {
+            author,
+            resolution,
+            location,
+            comment,
+            pr_url,
+        }
+    }
+
+    /// Returns the user that resolved the thread. Github does not say who unresolved a thread.
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    pub fn comment(&self) -> Option<&Comment> {
+        self.comment.as_ref()
+    }
+
+    /// Returns the url of the first comment of the thread, or of the PR if it is gone
+    pub fn url(&self) -> &str {
+        self.comment.as_ref().map_or(&self.pr_url, |x| &x.url)
+    }
+
+    /// Returns a one line summary of the change, e.g. "@user resolved a thread on src/main.rs:42"
+    pub fn headline(&self) -> String {
+        match (self.resolution, &self.author) {
+            (Resolution::Resolved, Some(author)) => {
+                format!("@{} resolved a thread on {}", author, self.location)
+            }
+            (Resolution::Resolved, None) => format!("Thread on {} was resolved", self.location),
+            (Resolution::Unresolved, _) => format!("Thread on {} was unresolved", self.location),
+        }
+    }
+
+    /// Returns the first comment of the thread, if it is still there
+    pub fn details(&self) -> Option<String> {
+        self.comment
+            .as_ref()
+            .map(|x| format!("@{} wrote:\n{}", x.author(), x.body()))
+    }
+}
+
+impl fmt::Display for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.headline())?;
+        if let Some(details) = self.details() {
+            writeln!(f)?;
+            writeln!(f, "{}", details)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The kind of feedback that was edited or deleted
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subject {
+    Comment,
+    Review,
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Comment => write!(f, "comment"),
+            Self::Review => write!(f, "review"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Edit {
+    author: String,
+    subject: Subject,
+    old_body: String,
+    new_body: String,
+    pub pr_url: String,
+    pub url: String,
+}
+
+impl Edit {
+    pub fn new(
+        author: String,
+        subject: Subject,
+        old_body: String,
+        new_body: String,
+        pr_url: String,
+        url: String,
+    ) -> Self {
+        Self {
+            author,
+            subject,
+            old_body,
+            new_body,
+            pr_url,
+            url,
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn subject(&self) -> Subject {
+        self.subject
+    }
+
+    pub fn old_body(&self) -> &str {
+        &self.old_body
+    }
+
+    pub fn new_body(&self) -> &str {
+        &self.new_body
+    }
+
+    /// Returns a one line summary of the edit, e.g. "@user edited their comment"
+    pub fn headline(&self) -> String {
+        format!("@{} edited their {}", self.author, self.subject)
+    }
+
+    /// Returns a line based diff of the old and new bodies, with removed lines prefixed by "-"
+    /// and added lines prefixed by "+"
+    pub fn details(&self) -> String {
+        TextDiff::from_lines(&self.old_body, &self.new_body)
+            .iter_all_changes()
+            .map(|x| {
+                let sign = match x.tag() {
+                    ChangeTag::Delete => "-",
+                    ChangeTag::Insert => "+",
+                    ChangeTag::Equal => " ",
+                };
+                format!("{} {}", sign, x.value().trim_end_matches('\n'))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:", self.headline())?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.details())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Deletion {
+    author: String,
+    body: String,
+}
+
+impl Deletion {
+    pub fn new(author: String, body: String) -> Self {
+        Self { author, body }
+    }
+
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// Returns a one line summary of the deletion, e.g. "Comment by @user was deleted"
+    pub fn headline(&self) -> String {
+        format!("Comment by @{} was deleted", self.author)
+    }
+}
+
+impl fmt::Display for Deletion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:", self.headline())?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(old_body: &str, new_body: &str) -> Edit {
+        Edit::new(
+            "reviewer".to_owned(),
+            Subject::Comment,
+            old_body.to_owned(),
+            new_body.to_owned(),
+            "https://github.com/org/repo/pull/42".to_owned(),
+            "https://github.com/org/repo/pull/42#issuecomment-7".to_owned(),
+        )
+    }
+
+    #[test]
+    fn edit_details_diff_the_lines() {
+        let edit = edit("first\nsecond\nthird\n", "first\nchanged\nthird\nfourth\n");
+
+        assert_eq!(
+            edit.details(),
+            "  first\n- second\n+ changed\n  third\n+ fourth"
+        );
+    }
+
+    #[test]
+    fn edit_of_a_single_line() {
+        assert_eq!(edit("old", "new").details(), "- old\n+ new");
+    }
+
+    #[test]
+    fn edit_headline_names_the_subject() {
+        assert_eq!(edit("a", "b").headline(), "@reviewer edited their comment");
+    }
+
+    fn review_comment(line: usize, diff_hunk: &str) -> ReviewComment {
+        ReviewComment {
+            path: "src/main.rs".to_owned(),
+            line: Some(line),
+            diff_hunk: diff_hunk.to_owned(),
+            in_reply_to_id: None,
+            body: format!("Comment on line {}", line),
+            created_at: "2023-06-01T00:00:00Z".to_owned(),
+        }
+    }
+
+    fn review(body: &str, comments: usize) -> Review {
+        let mut review = Review::new(
+            "reviewer".to_owned(),
+            ReviewState::Commented,
+            body.to_owned(),
+            "2023-06-01T00:00:00Z".to_owned(),
+            "https://github.com/org/repo/pull/42".to_owned(),
+            "https://github.com/org/repo/pull/42#pullrequestreview-7".to_owned(),
+        );
+        for line in 1..=comments {
+            review.add_comment(review_comment(line, "@@ -1,2 +1,2 @@\n-old\n+new"));
+        }
+        review
+    }
+
+    const LIMITS: settings::ReviewComments = settings::ReviewComments {
+        max_comments: 2,
+        max_hunk_lines: 2,
+    };
+
+    #[test]
+    fn hunk_keeps_the_last_lines_without_the_header() {
+        let comment = review_comment(3, "@@ -1,4 +1,4 @@\n first\n-second\n+changed\n third");
+
+        assert_eq!(comment.hunk(2), vec!["+changed", " third"]);
+        assert_eq!(
+            comment.hunk(10),
+            vec![" first", "-second", "+changed", " third"]
+        );
+        assert!(comment.hunk(0).is_empty());
+    }
+
+    #[test]
+    fn paragraphs_show_the_body_and_each_comment() {
+        assert_eq!(
+            review("Looks good", 1).paragraphs(LIMITS),
+            vec![
+                "Looks good".to_owned(),
+                "src/main.rs:1:\n-old\n+new\nComment on line 1".to_owned(),
+            ]
+        );
+        assert_eq!(review("", 0).paragraphs(LIMITS), Vec::<String>::default());
+    }
+
+    #[test]
+    fn paragraphs_are_truncated_to_max_comments() {
+        let paragraphs = review("", 3).paragraphs(LIMITS);
+        assert_eq!(paragraphs.len(), 3);
+        assert!(paragraphs[1].starts_with("src/main.rs:2:"));
+        assert_eq!(paragraphs[2], "(+ 1 more comment)");
+
+        let paragraphs = review("", 5).paragraphs(LIMITS);
+        assert_eq!(paragraphs[2], "(+ 3 more comments)");
+    }
+}
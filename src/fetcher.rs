@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+
+use crate::clients::cache::{self, PullRequest, Response};
+use crate::clients::github::{
+    self, CheckRunsResponse, CombinedStatus, GithubClient, Issue, ResponseCache,
+};
+use crate::clients::graphql::{self, GraphqlClient, ReviewThread};
+use crate::settings::{self, Settings};
+
+/// Everything fetched from Github about a PR that the notifications are based on
+#[derive(Debug)]
+pub struct PullRequestData {
+    pub details: github::PullRequest,
+    pub comments: Vec<github::Comment>,
+    pub reviews: Vec<github::Review>,
+    pub review_comments: Vec<github::ReviewComment>,
+    // the REST fetcher skips the commits when the head has not moved since the last poll
+    pub commits: Option<Vec<github::Commit>>,
+    // the REST fetcher skips CI for PRs of other users
+    pub combined_status: Option<CombinedStatus>,
+    pub check_runs: Option<CheckRunsResponse>,
+    // `None` when the threads could not be fetched, their state is carried over from the last poll
+    pub review_threads: Option<Vec<ReviewThread>>,
+    // the REST responses to cache for conditional requests in the next poll
+    pub responses: HashMap<String, Response>,
+}
+
+/// Fetches the data of each of the given PRs with the configured fetcher. The PRs are fetched
+/// independently of each other, a PR that fails to be fetched does not affect the rest. Up to
+/// `github.concurrency` PRs, or batches of PRs with GraphQL, are fetched at the same time, so the
+/// results come back in no particular order.
+pub async fn fetch(
+    settings: &Settings,
+    github_client: &GithubClient,
+    graphql_client: &GraphqlClient,
+    prs_by_ids: &HashMap<usize, Issue>,
+    current_data: &cache::Data,
+) -> HashMap<usize, Result<PullRequestData>> {
+    let concurrency = settings.github.concurrency.max(1);
+
+    match settings.github.fetcher {
+        settings::Fetcher::Rest => {
+            stream::iter(prs_by_ids)
+                .map(|(id, pr)| async move {
+                    let data = rest(
+                        github_client,
+                        graphql_client,
+                        pr,
+                        current_data.get(id),
+                        &settings.github,
+                    )
+                    .await;
+                    (*id, data)
+                })
+                .buffer_unordered(concurrency)
+                .collect()
+                .await
+        }
+        settings::Fetcher::Graphql => {
+            let mut ret = HashMap::default();
+            let mut prs: Vec<&Issue> = Vec::default();
+            let mut keys: Vec<(String, String, usize)> = Vec::default();
+            for pr in prs_by_ids.values() {
+                match pr
+                    .repo_owner()
+                    .and_then(|repo_owner| Ok((repo_owner, pr.repo_name()?, pr.number)))
+                {
+                    Ok(key) => {
+                        prs.push(pr);
+                        keys.push(key);
+                    }
+                    // a PR whose repo can't be told from its url only fails by itself
+                    Err(e) => {
+                        ret.insert(pr.id, Err(e));
+                    }
+                }
+            }
+
+            let batches = prs
+                .chunks(graphql::BATCH_SIZE)
+                .zip(keys.chunks(graphql::BATCH_SIZE));
+            let fetched: Vec<(usize, Result<PullRequestData>)> = stream::iter(batches)
+                .map(|(batch, keys)| async move {
+                    graphql_batch(
+                        settings,
+                        github_client,
+                        graphql_client,
+                        batch,
+                        keys,
+                        current_data,
+                    )
+                    .await
+                })
+                .buffer_unordered(concurrency)
+                .flat_map(stream::iter)
+                .collect()
+                .await;
+            ret.extend(fetched);
+
+            ret
+        }
+    }
+}
+
+/// Fetches the data of a batch of PRs with a single GraphQL query, falling back to REST for the
+/// PRs that don't fit in it
+async fn graphql_batch(
+    settings: &Settings,
+    github_client: &GithubClient,
+    graphql_client: &GraphqlClient,
+    batch: &[&Issue],
+    keys: &[(String, String, usize)],
+    current_data: &cache::Data,
+) -> Vec<(usize, Result<PullRequestData>)> {
+    debug!("Fetching {} PRs with GraphQL", keys.len());
+
+    // a failed batch is retried one PR at a time, so that a single bad PR only fails by itself
+    let batch_data = match graphql_client.pull_requests(keys).await {
+        Ok(batch_data) => batch_data,
+        Err(e) => {
+            warn!("GraphQL batch failed, falling back to REST: {}", e);
+            batch.iter().map(|_| None).collect()
+        }
+    };
+
+    let mut ret = Vec::default();
+    for (pr, data) in batch.iter().zip(batch_data) {
+        let data = match data {
+            Some(data) => Ok(data),
+            None => {
+                rest(
+                    github_client,
+                    graphql_client,
+                    pr,
+                    current_data.get(&pr.id),
+                    &settings.github,
+                )
+                .await
+            }
+        };
+        ret.push((pr.id, data));
+    }
+
+    ret
+}
+
+/// Fetches the data of the PR with one REST call per kind of data
+async fn rest(
+    github_client: &GithubClient,
+    graphql_client: &GraphqlClient,
+    pr: &Issue,
+    current: Option<&PullRequest>,
+    github_settings: &settings::Github,
+) -> Result<PullRequestData> {
+    let repo_owner = pr.repo_owner()?;
+    let repo_name = pr.repo_name()?;
+    let mut cache = ResponseCache::new(current.map(|x| x.responses.clone()).unwrap_or_default());
+
+    let details = github_client
+        .pull_request(&repo_owner, &repo_name, pr.number, &mut cache)
+        .await?;
+    // deleted comments are only noticed if all the comments are fetched every time
+    let comments = github_client
+        .issue_comments(
+            &repo_owner,
+            &repo_name,
+            pr.number,
+            !github_settings.notify_deleted_comments,
+            &mut cache,
+        )
+        .await?;
+    let reviews = github_client
+        .reviews(&repo_owner, &repo_name, pr.number, &mut cache)
+        .await?;
+    let review_comments = github_client
+        .review_comments(&repo_owner, &repo_name, pr.number, &mut cache)
+        .await?;
+
+    // the head has not moved since the last poll, no need to fetch the commits again
+    let commits = match current.and_then(|x| x.head_sha.as_ref()) == Some(&details.head.sha) {
+        true => None,
+        false => Some(
+            github_client
+                .commits(&repo_owner, &repo_name, pr.number, &mut cache)
+                .await?,
+        ),
+    };
+
+    let (combined_status, check_runs) = match pr.user.login == github_settings.username {
+        true => (
+            Some(
+                github_client
+                    .combined_status(&repo_owner, &repo_name, &details.head.sha, &mut cache)
+                    .await?,
+            ),
+            Some(
+                github_client
+                    .check_runs(&repo_owner, &repo_name, &details.head.sha, &mut cache)
+                    .await?,
+            ),
+        ),
+        false => (None, None),
+    };
+
+    // thread resolution is only available through GraphQL. It is only notified for threads that
+    // the user started or that are on the user's own PRs, the other PRs don't need the query.
+    let username = github_settings.username.as_str();
+    let involved = pr.user.login == username
+        || review_comments
+            .iter()
+            .any(|x| x.in_reply_to_id.is_none() && x.user.login == username);
+    let review_threads = match involved {
+        true => match graphql_client
+            .review_threads(&repo_owner, &repo_name, pr.number)
+            .await
+        {
+            Ok(review_threads) => Some(review_threads),
+            // the rest of the PR is still worth notifying about without them
+            Err(e) => {
+                warn!(
+                    "Could not fetch the review threads of PR {}: {:?}",
+                    pr.html_url, e
+                );
+                None
+            }
+        },
+        false => Some(Vec::default()),
+    };
+
+    Ok(PullRequestData {
+        details,
+        comments,
+        reviews,
+        review_comments,
+        commits,
+        combined_status,
+        check_runs,
+        review_threads,
+        responses: cache.into_responses(),
+    })
+}
@@ -1,17 +1,37 @@
-use std::collections::HashMap;
-
-use crate::clients::cache::{CacheClient, PullRequest};
-use crate::clients::github::{GithubClient, Issue, ReviewState};
-use crate::clients::ntfy::NtfyClient;
-use crate::feedback::{Comment, Review};
-use anyhow::Result;
-use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::Duration;
+
+use crate::clients::cache::{self, Assignments, Content, PullRequest, State, Store};
+use crate::clients::github::{
+    self, CheckRunConclusion, CheckRunStatus, CheckRunsResponse, CombinedStatus, GithubClient,
+    GithubError, Issue, ResponseCache, ReviewState, StatusState,
+};
+use crate::clients::graphql::{GraphqlClient, ReviewThread};
+use crate::clients::rate_limit::RateLimit;
+use crate::event::{Event, EventKind};
+use crate::feedback::{
+    Assignment, Ci, CiStatus, Comment, Commit, Deletion, Edit, Push, Reply, Resolution, Review,
+    StateChange, Subject, Thread, Transition,
+};
+use crate::fetcher::PullRequestData;
+use crate::notifier::Notifier;
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
 use regex::Regex;
 use settings::Settings;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::time;
 
 mod clients;
+mod event;
 mod feedback;
+mod fetcher;
+mod notifier;
 mod settings;
+#[cfg(test)]
+mod testing;
 mod util;
 
 #[tokio::main]
@@ -21,7 +41,10 @@ async fn main() -> Result<()> {
     info!("Starting prnotify");
 
     // parse settings
-    let settings = Settings::try_new()?;
+    let mut settings = Settings::try_new()?;
+    if env::args().skip(1).any(|x| x == "--daemon") {
+        settings.poll.daemon = true;
+    }
 
     // if settings are specified, extract cookies from firefox local storage
     let cookies = match &settings.firefox {
@@ -40,88 +63,464 @@ async fn main() -> Result<()> {
         .map(|x| Regex::new(x))
         .collect::<Result<Vec<Regex>, _>>()?;
 
-    // initialize clients
-    let cache_client = CacheClient::new(settings.cache.path);
-    let ntfy_client = NtfyClient::try_new(settings.ntfy.base_url, settings.ntfy.topic)?;
-    let github_client = GithubClient::try_new(
+    let mention_pattern = mention_pattern(&settings.github)?;
+
+    // initialize clients, these are reused across polls in daemon mode
+    let store = cache::from_settings(&settings.cache)?;
+    let notifiers = notifier::from_settings(&settings.notifiers, settings.review_comments)?;
+    let http_client = github::http_client(
         &settings.github.personal_access_token,
-        format!("https://{}", settings.github.hostname),
         cookies,
-        settings.github.proxy_url,
+        settings.github.proxy_url.clone(),
     )?;
+    let github_client = GithubClient::new(
+        http_client.clone(),
+        format!("https://{}", settings.github.hostname),
+    );
+    let graphql_client = GraphqlClient::new(
+        http_client,
+        GraphqlClient::url_for(&settings.github.hostname),
+    );
+
+    if !settings.poll.daemon {
+        poll(
+            &settings,
+            &exclude_comment_patterns,
+            &mention_pattern,
+            store.as_ref(),
+            &notifiers,
+            &github_client,
+            &graphql_client,
+        )
+        .await?;
+
+        info!("Done");
+        return Ok(());
+    }
+
+    // listen for shutdown signals in the background, so that a signal received in the middle of
+    // a poll lets the poll finish and write the cache before exiting. The listeners are set up
+    // here so that failing to do so stops the daemon before it starts.
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigint.recv() => {}
+            _ = sigterm.recv() => {}
+        }
+        info!("Received shutdown signal, exiting after the current poll");
+        let _ = shutdown_tx.send(true);
+    });
+
+    info!(
+        "Running in daemon mode, polling every {}s (+ up to {}s jitter)",
+        settings.poll.interval, settings.poll.jitter
+    );
+
+    loop {
+        // polls run one after another, so they never overlap
+        if let Err(e) = poll(
+            &settings,
+            &exclude_comment_patterns,
+            &mention_pattern,
+            store.as_ref(),
+            &notifiers,
+            &github_client,
+            &graphql_client,
+        )
+        .await
+        {
+            error!("Poll failed: {:?}", e);
+        }
+
+        if *shutdown_rx.borrow() {
+            break;
+        }
+
+        let delay =
+            Duration::from_secs(settings.poll.interval + fastrand::u64(0..=settings.poll.jitter));
+        debug!("Next poll in {:?}", delay);
+
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown_rx.changed() => break,
+        }
+    }
 
-    // read data stored in cache
-    let current_data = cache_client.read().unwrap_or_default();
+    info!("Done");
+    Ok(())
+}
+
+/// Runs a single pass over the watched pull requests, sending notifications for anything new and
+/// writing the results to the cache
+async fn poll(
+    settings: &Settings,
+    exclude_comment_patterns: &[Regex],
+    mention_pattern: &Regex,
+    store: &dyn Store,
+    notifiers: &[Box<dyn Notifier>],
+    github_client: &GithubClient,
+    graphql_client: &GraphqlClient,
+) -> Result<()> {
+    // read data stored in cache. Without it every PR would look new, so unless configured
+    // otherwise the poll only records the current state as a baseline for the next one.
+    let (current_data, scratch) = match store.read() {
+        Ok(Some(current_data)) => (current_data, false),
+        Ok(None) => {
+            info!("No cache yet, starting from scratch");
+            (cache::Data::default(), true)
+        }
+        Err(e) => {
+            error!("Could not read the cache, starting from scratch: {:?}", e);
+            (cache::Data::default(), true)
+        }
+    };
+    let baseline = scratch && settings.cache.baseline;
+    if baseline {
+        info!("Recording the current state of the PRs without sending notifications");
+    } else if scratch {
+        // the PRs are stored one by one as they are notified, on top of an empty state
+        store.write(&cache::Data::default())?;
+    }
     let mut new_data = HashMap::default();
 
+    match github_client.update_rate_limits().await {
+        Ok(()) => (),
+        // the rate limits can be checked with any valid token, no other request could succeed
+        Err(e @ (GithubError::Auth { .. } | GithubError::Forbidden { .. })) => return Err(e.into()),
+        Err(e) => warn!("Could not fetch the Github rate limits: {:?}", e),
+    }
+    // the budgets of every resource come from the REST API, the GraphQL client needs its own to
+    // wait for the reset instead of sending requests that are bound to fail
+    if let Some(rate_limit) = github_client.rate_limiter().get("graphql")? {
+        graphql_client.rate_limiter().set("graphql", rate_limit)?;
+    }
+
+    // low priority queries are skipped when running them could use up the rest of the budget
+    let resource = match settings.github.fetcher {
+        settings::Fetcher::Rest => "core",
+        settings::Fetcher::Graphql => "graphql",
+    };
+    let budget = github_client.rate_limiter().get(resource)?;
+    let skip_low_priority = skip_low_priority(&settings.github, budget);
+    if skip_low_priority {
+        warn!(
+            "Only {} requests left in the Github {} rate limit, skipping low priority queries",
+            budget.map_or(0, |x| x.remaining),
+            resource
+        );
+    }
+
     // get relevant pull requests from github
     let mut prs_by_ids: HashMap<usize, Issue> = HashMap::default();
-    for query in settings.github.queries {
-        let prs_response = github_client.pull_requests(&query).await?;
+    let low_priority_queries = match skip_low_priority {
+        true => &[][..],
+        false => &settings.github.low_priority_queries[..],
+    };
+    // when a query fails, the PRs it would have returned are unknown, so none are dropped
+    let mut incomplete = skip_low_priority;
+    for query in settings.github.queries.iter().chain(low_priority_queries) {
+        let prs_response = match github_client.pull_requests(query).await {
+            Ok(prs_response) => prs_response,
+            // the search is not tied to any repo, being denied means the token itself is unusable
+            Err(e @ (GithubError::Auth { .. } | GithubError::Forbidden { .. })) => {
+                return Err(e.into())
+            }
+            Err(e) => {
+                error!("Could not search for PRs with query {:?}: {:?}", query, e);
+                incomplete = true;
+                continue;
+            }
+        };
+        // the PRs missing from a truncated search may still be watched, so none are dropped
+        incomplete |= prs_response.is_truncated();
         for pr in prs_response.items {
             prs_by_ids.entry(pr.id).or_insert(pr);
         }
     }
 
-    for (_, pr) in prs_by_ids {
-        // get comments, reviews, and review comments from github for the current PR
-        let comments_by_ids = comments_by_ids(
-            &github_client,
-            &pr,
-            settings.github.username.as_str(),
-            &exclude_comment_patterns,
-        )
-        .await?;
-        let reviews_by_ids = reviews_by_ids(
-            &github_client,
-            &pr,
-            settings.github.username.as_str(),
-            &exclude_comment_patterns,
-        )
-        .await?;
+    // PRs that are no longer returned by any of the queries are dropped from the cache. Check them
+    // one last time first, to find out if they dropped out because they were merged or closed.
+    for (id, current) in &current_data {
+        if prs_by_ids.contains_key(id) {
+            continue;
+        }
 
-        // do notifications
-        send_notifications(
-            &ntfy_client,
-            &current_data,
-            &reviews_by_ids,
-            &comments_by_ids,
-            &pr,
-        )
-        .await?;
+        // the PR may only be returned by a query that was skipped or failed, keep it as is
+        if incomplete {
+            new_data.insert(*id, current.clone());
+            continue;
+        }
+
+        // PRs cached before their info was tracked cannot be looked up anymore
+        let Some(info) = &current.info else {
+            continue;
+        };
+
+        let details = match github_client
+            .pull_request(
+                &info.repo_owner,
+                &info.repo_name,
+                info.number,
+                &mut ResponseCache::new(current.responses.clone()),
+            )
+            .await
+        {
+            Ok(details) => details,
+            Err(e @ GithubError::Auth { .. }) => return Err(e.into()),
+            // the PR or its repo was deleted, there is nothing left to notify about
+            Err(e @ GithubError::NotFound { .. }) => {
+                warn!("Dropping PR {}: {}", info.url, e);
+                continue;
+            }
+            Err(e) => {
+                // try again in the next poll rather than dropping it without a notification
+                warn!(
+                    "Could not fetch PR {} before dropping it: {:?}",
+                    info.url, e
+                );
+                new_data.insert(*id, current.clone());
+                continue;
+            }
+        };
+
+        let state_change = state_change(Some(current), &details, settings.github.username.as_str());
+        if let Some(state_change) = state_change {
+            debug!("Sending notification for state change: {:?}", state_change);
+            let event = Event::new(info.clone(), EventKind::StateChange(state_change));
+            if let Err(e) = deliver(notifiers, store, &[event]).await {
+                // keep the PR, so that the notification is tried again in the next poll
+                error!(
+                    "Could not notify the state change of PR {}: {:?}",
+                    info.url, e
+                );
+                new_data.insert(*id, current.clone());
+                continue;
+            }
+
+            // the PR is only dropped at the end of the poll, store the new state until then so
+            // that it is not notified again if the poll fails before that
+            let notified = PullRequest {
+                state: Some(State::from(&details)),
+                draft: details.draft,
+                ..current.clone()
+            };
+            store.write_pull_request(*id, &notified)?;
+        }
+    }
 
-        // add to cache data, to be saved to file later
-        new_data.insert(
-            pr.id,
-            PullRequest {
-                reviews: reviews_by_ids.keys().copied().collect(),
-                comments: comments_by_ids.keys().copied().collect(),
+    // get comments, reviews, commits, CI and threads from github for all the PRs
+    let mut data_by_ids = fetcher::fetch(
+        settings,
+        github_client,
+        graphql_client,
+        &prs_by_ids,
+        &current_data,
+    )
+    .await;
+
+    // the fetches finish in any order, process the PRs in the order of their last activity so that
+    // the notifications are sent in the same order every time
+    let mut prs: Vec<Issue> = prs_by_ids.into_values().collect();
+    prs.sort_by(|a, b| (&a.updated_at, a.id).cmp(&(&b.updated_at, b.id)));
+
+    for pr in prs {
+        let current = current_data.get(&pr.id);
+        let result = match data_by_ids.remove(&pr.id) {
+            Some(Ok(data)) => process_pull_request(
+                settings,
+                exclude_comment_patterns,
+                mention_pattern,
+                &current_data,
+                &pr,
+                data,
+            ),
+            Some(Err(e)) => Err(e),
+            None => Err(anyhow!("Missing data")),
+        };
+        let result = match result {
+            Ok((cached, events)) if baseline || events.is_empty() => Ok(cached),
+            // stored as soon as it is notified, so that a later failure can't notify it again. The
+            // other PRs are stored all at once at the end of the poll.
+            Ok((cached, events)) => match deliver(notifiers, store, &events).await {
+                Ok(()) => store.write_pull_request(pr.id, &cached).map(|_| cached),
+                Err(e) => Err(e),
             },
-        );
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(cached) => {
+                new_data.insert(pr.id, cached);
+            }
+            // without a working token none of the other PRs can be fetched either
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(e) => {
+                // keep what was cached, so the PR is picked up where it left off in the next poll
+                error!("Could not process PR {}: {:?}", pr.html_url, e);
+                if let Some(current) = current {
+                    new_data.insert(pr.id, current.clone());
+                }
+            }
+        }
     }
 
-    cache_client.write(&new_data)?;
+    // the events were already sent to the notifiers that don't batch them up, so the PRs are
+    // stored either way. Notifiers that could not flush retry on their next flush.
+    let flushed = notifier::flush_all(notifiers).await;
 
-    info!("Done");
-    Ok(())
+    // store the PRs that had nothing to notify, and drop the ones that are no longer watched
+    store.write(&new_data)?;
+
+    github_client.rate_limiter().log()?;
+    graphql_client.rate_limiter().log()?;
+
+    flushed
 }
 
-async fn reviews_by_ids(
-    github_client: &GithubClient,
+/// Returns whether the low priority queries should be skipped, because the budget that is left
+/// is below the reserve. Without a known budget the queries are run.
+fn skip_low_priority(github_settings: &settings::Github, budget: Option<RateLimit>) -> bool {
+    !github_settings.low_priority_queries.is_empty()
+        && matches!(budget, Some(x) if x.remaining < github_settings.rate_limit_reserve)
+}
+
+/// Returns what should be cached for a PR based on its fetched data, along with the notifications
+/// to send for it
+fn process_pull_request(
+    settings: &Settings,
+    exclude_comment_patterns: &[Regex],
+    mention_pattern: &Regex,
+    current_data: &cache::Data,
     pr: &Issue,
-    username: &str,
-    exclude_comment_patterns: &Vec<Regex>,
-) -> Result<HashMap<usize, Review>> {
-    let repo_owner = pr.repo_owner()?;
-    let repo_name = pr.repo_name()?;
+    data: PullRequestData,
+) -> Result<(PullRequest, Vec<Event>)> {
+    let PullRequestData {
+        details,
+        comments,
+        reviews,
+        review_comments,
+        commits,
+        combined_status,
+        check_runs,
+        review_threads,
+        responses,
+    } = data;
+    let current = current_data.get(&pr.id);
+    let username = settings.github.username.as_str();
 
-    let reviews_response = github_client
-        .reviews(&repo_owner, &repo_name, pr.number)
-        .await?;
-    let review_comments_response = github_client
-        .review_comments(&repo_owner, &repo_name, pr.number)
-        .await?;
+    // comments that are filtered out still exist, they are not deleted
+    let comment_ids: HashSet<usize> = comments.iter().map(|x| x.id).collect();
+    let comments_by_ids = comments_by_ids(pr, comments, username, exclude_comment_patterns);
+    let has_reviewed = reviews
+        .iter()
+        .any(|x| x.state != ReviewState::Pending && x.user.login == username);
+    let replies_by_ids = replies_by_ids(pr, &review_comments, username, exclude_comment_patterns);
+    let reviews_by_ids = reviews_by_ids(
+        pr,
+        reviews,
+        review_comments,
+        username,
+        exclude_comment_patterns,
+    );
+
+    let (commits, push) = self::commits(pr, &details, commits, current, username);
+    let state_change = state_change(current, &details, username);
+    let (ci_status, ci) = ci(pr, &details, combined_status, check_runs, current, username);
+    let (assignments, assignment_events) =
+        assignments(pr, &details, current, has_reviewed, &settings.github)?;
+    let (threads, thread_events) = threads(pr, review_threads, current, username);
+
+    let updates = assignment_events
+        .into_iter()
+        .map(EventKind::Assignment)
+        .chain(thread_events.into_iter().map(EventKind::Thread))
+        .chain(push.map(EventKind::Push))
+        .chain(state_change.map(EventKind::StateChange))
+        .chain(ci.map(EventKind::Ci))
+        .collect();
+
+    let events = notifications(
+        mention_pattern,
+        settings.github.notify_deleted_comments,
+        current_data,
+        &reviews_by_ids,
+        &comments_by_ids,
+        &comment_ids,
+        &replies_by_ids,
+        updates,
+        pr,
+    )?;
+
+    // add to cache data, to be saved to file later
+    let cached = PullRequest {
+        reviews: reviews_by_ids
+            .iter()
+            .map(|(k, v)| {
+                let content = Content {
+                    author: v.author().to_owned(),
+                    body: v.body().unwrap_or_default().to_owned(),
+                };
+                (*k, Some(content))
+            })
+            .collect(),
+        comments: comments_by_ids
+            .iter()
+            .map(|(k, v)| {
+                let content = Content {
+                    author: v.author().to_owned(),
+                    body: v.body().to_owned(),
+                };
+                (*k, Some(content))
+            })
+            .collect(),
+        replies: Some(replies_by_ids.keys().copied().collect()),
+        threads: Some(threads),
+        info: Some(event::PullRequest::try_from(pr)?),
+        state: Some(State::from(&details)),
+        draft: details.draft,
+        head_sha: Some(details.head.sha),
+        commits,
+        ci: ci_status,
+        assignments: Some(assignments),
+        responses,
+    };
+
+    Ok((cached, events))
+}
+
+/// Sends the events to every notifier one after another, and records each delivery in the store
+async fn deliver(
+    notifiers: &[Box<dyn Notifier>],
+    store: &dyn Store,
+    events: &[Event],
+) -> Result<()> {
+    for event in events {
+        let result = notifier::notify_all(notifiers, event).await;
+        store.record_delivery(event, result.as_ref().err())?;
+        result?;
+    }
+
+    Ok(())
+}
 
+/// Returns whether the error means that polling can't go on at all, rather than only failing for
+/// the PR it happened on. A PR that the token is not allowed to access only fails that PR.
+fn is_fatal(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<GithubError>(),
+        Some(GithubError::Auth { .. })
+    )
+}
+
+fn reviews_by_ids(
+    pr: &Issue,
+    reviews_response: Vec<github::Review>,
+    review_comments_response: Vec<github::ReviewComment>,
+    username: &str,
+    exclude_comment_patterns: &[Regex],
+) -> HashMap<usize, Review> {
     let mut ret: HashMap<usize, Review> = reviews_response
         .into_iter()
         .filter(|x| x.state != ReviewState::Pending && x.user.login != username)
@@ -133,6 +532,7 @@ async fn reviews_by_ids(
                     x.user.login,
                     x.state.into(),
                     x.body,
+                    x.submitted_at.unwrap_or_default(),
                     pr.html_url.clone(),
                     x.html_url,
                 ),
@@ -140,94 +540,1473 @@ async fn reviews_by_ids(
         })
         .collect();
 
-    // attach review comments to their reviews
+    // attach review comments to their reviews, replies are notified on their own
     for review_comment in review_comments_response {
-        ret.entry(review_comment.pull_request_review_id)
-            .and_modify(|e| e.add_comment(review_comment.body));
+        if review_comment.in_reply_to_id.is_none() {
+            ret.entry(review_comment.pull_request_review_id)
+                .and_modify(|e| e.add_comment((&review_comment).into()));
+        }
     }
 
-    Ok(ret)
+    // replying to a thread creates a review that only contains the reply, drop those reviews so
+    // the reply is not notified twice
+    ret.retain(|_, v| {
+        !matches!(v.state(), feedback::ReviewState::Commented)
+            || v.body().is_some()
+            || !v.comments().is_empty()
+    });
+
+    ret
 }
 
-async fn comments_by_ids(
-    github_client: &GithubClient,
+/// Returns the replies to review comment threads by their ids
+fn replies_by_ids(
     pr: &Issue,
+    review_comments_response: &[github::ReviewComment],
     username: &str,
-    exclude_comment_patterns: &Vec<Regex>,
-) -> Result<HashMap<usize, Comment>> {
-    let repo_owner = pr.repo_owner()?;
-    let repo_name = pr.repo_name()?;
-
-    let comments_response = github_client
-        .issue_comments(&repo_owner, &repo_name, pr.number)
-        .await?;
+    exclude_comment_patterns: &[Regex],
+) -> HashMap<usize, Reply> {
+    review_comments_response
+        .iter()
+        .filter(|x| x.user.login != username)
+        .filter(|x| !is_comment_filtered(&x.body, exclude_comment_patterns))
+        .filter_map(|x| {
+            let parent_id = x.in_reply_to_id?;
+            let parent = review_comments_response
+                .iter()
+                .find(|y| y.id == parent_id)
+                .map(|y| {
+                    Comment::new(
+                        y.user.login.clone(),
+                        y.body.clone(),
+                        y.created_at.clone(),
+                        pr.html_url.clone(),
+                        y.html_url.clone(),
+                    )
+                });
+            let reply = Reply::new(
+                x.user.login.clone(),
+                x.into(),
+                parent,
+                pr.html_url.clone(),
+                x.html_url.clone(),
+            );
+            Some((x.id, reply))
+        })
+        .collect()
+}
 
-    let ret: HashMap<usize, Comment> = comments_response
+fn comments_by_ids(
+    pr: &Issue,
+    comments_response: Vec<github::Comment>,
+    username: &str,
+    exclude_comment_patterns: &[Regex],
+) -> HashMap<usize, Comment> {
+    comments_response
         .into_iter()
         .filter(|x| x.user.login != username)
         .filter(|x| !is_comment_filtered(&x.body, exclude_comment_patterns))
         .map(|x| {
             (
                 x.id,
-                Comment::new(x.user.login, x.body, pr.html_url.clone(), x.html_url),
+                Comment::new(
+                    x.user.login,
+                    x.body,
+                    x.created_at,
+                    pr.html_url.clone(),
+                    x.html_url,
+                ),
             )
         })
+        .collect()
+}
+
+/// Returns the shas of the commits of the PR, along with the push that happened since the last
+/// poll if there was one
+fn commits(
+    pr: &Issue,
+    details: &github::PullRequest,
+    commits_response: Option<Vec<github::Commit>>,
+    current: Option<&PullRequest>,
+    username: &str,
+) -> (Vec<String>, Option<Push>) {
+    // the head has not moved since the last poll, the commits are the same
+    if let Some(current) = current {
+        if current.head_sha.as_ref() == Some(&details.head.sha) {
+            return (current.commits.clone(), None);
+        }
+    }
+
+    let commits_response = commits_response.unwrap_or_default();
+    let shas: Vec<String> = commits_response.iter().map(|x| x.sha.clone()).collect();
+
+    // new PRs, and PRs cached before heads were tracked, have nothing to compare against
+    let Some(current) = current else {
+        return (shas, None);
+    };
+    let Some(previous_head) = &current.head_sha else {
+        return (shas, None);
+    };
+
+    // if the previous head is no longer part of the PR, its history was rewritten
+    let forced = !shas.contains(previous_head);
+    let pushed: Vec<Commit> = commits_response
+        .iter()
+        .filter(|x| !current.commits.contains(&x.sha))
+        .map(|x| Commit {
+            sha: x.sha.clone(),
+            message: x.commit.message.clone(),
+        })
+        .collect();
+
+    // the pusher is not known, assume it is the author of the new head
+    let author = commits_response
+        .last()
+        .and_then(|x| x.author.as_ref())
+        .map_or(&pr.user.login, |x| &x.login);
+
+    // a force-push can also only drop or rewrite commits back to ones that were seen before,
+    // which is still worth knowing about
+    if (pushed.is_empty() && !forced) || author == username {
+        return (shas, None);
+    }
+
+    let push = Push::new(author.clone(), pushed, forced, pr.html_url.clone());
+    (shas, Some(push))
+}
+
+/// Returns the change of the state of the PR since the last poll, if there was one
+fn state_change(
+    current: Option<&PullRequest>,
+    details: &github::PullRequest,
+    username: &str,
+) -> Option<StateChange> {
+    // new PRs, and PRs cached before states were tracked, have nothing to compare against
+    let current = current?;
+    let previous_state = current.state?;
+
+    let transition = match (previous_state, State::from(details)) {
+        (State::Open, State::Merged) => Transition::Merged,
+        (State::Open, State::Closed) => Transition::Closed,
+        (State::Closed | State::Merged, State::Open) => Transition::Reopened,
+        (State::Open, State::Open) if !current.draft && details.draft => {
+            Transition::ConvertedToDraft
+        }
+        (State::Open, State::Open) if current.draft && !details.draft => Transition::ReadyForReview,
+        _ => return None,
+    };
+
+    // only merges say who made them
+    let author = match transition {
+        Transition::Merged => details.merged_by.as_ref().map(|x| x.login.clone()),
+        _ => None,
+    };
+
+    if author.as_deref() == Some(username) {
+        return None;
+    }
+
+    Some(StateChange::new(author, transition))
+}
+
+/// Returns the CI status of the head of the PR, along with the CI result if CI finished since the
+/// last poll. CI is only checked for the user's own PRs.
+fn ci(
+    pr: &Issue,
+    details: &github::PullRequest,
+    combined_status: Option<CombinedStatus>,
+    check_runs_response: Option<CheckRunsResponse>,
+    current: Option<&PullRequest>,
+    username: &str,
+) -> (Option<cache::Ci>, Option<Ci>) {
+    if pr.user.login != username {
+        return (None, None);
+    }
+
+    let sha = &details.head.sha;
+    let statuses = combined_status.map(|x| x.statuses).unwrap_or_default();
+    let check_runs = check_runs_response
+        .map(|x| x.check_runs)
+        .unwrap_or_default();
+
+    // the PR does not have any CI set up
+    if statuses.is_empty() && check_runs.is_empty() {
+        return (None, None);
+    }
+
+    let mut failed_checks = Vec::default();
+    let mut pending = false;
+    for status in statuses {
+        match status.state {
+            StatusState::Error | StatusState::Failure => failed_checks.push(status.context),
+            StatusState::Pending => pending = true,
+            StatusState::Success => {}
+        }
+    }
+    for check_run in check_runs {
+        match (check_run.status, check_run.conclusion) {
+            (CheckRunStatus::Completed, Some(CheckRunConclusion::Failure)) => {
+                failed_checks.push(check_run.name)
+            }
+            (CheckRunStatus::Completed, _) => {}
+            (CheckRunStatus::Incomplete, _) => pending = true,
+        }
+    }
+
+    // report failures as soon as they happen instead of waiting for the other checks to finish
+    let status = if !failed_checks.is_empty() {
+        CiStatus::Failure
+    } else if pending {
+        CiStatus::Pending
+    } else {
+        CiStatus::Success
+    };
+
+    let finished = status != CiStatus::Pending
+        && match current.and_then(|x| x.ci.as_ref()) {
+            // CI was not seen for this PR before, there is nothing to compare against
+            None => false,
+            Some(previous) if previous.sha == *sha => previous.status != status,
+            // there was a push since the last poll, so CI for the new head was pending before
+            Some(_) => true,
+        };
+
+    let ci = finished.then(|| Ci::new(status, failed_checks, pr.html_url.clone()));
+    let ci_status = cache::Ci {
+        sha: sha.clone(),
+        status,
+    };
+
+    (Some(ci_status), ci)
+}
+
+/// Returns the reviewers, teams and assignees that the PR is waiting on, along with the review
+/// requests and assignments for the user that were made since the last poll
+fn assignments(
+    pr: &Issue,
+    details: &github::PullRequest,
+    current: Option<&PullRequest>,
+    has_reviewed: bool,
+    github_settings: &settings::Github,
+) -> Result<(Assignments, Vec<Assignment>)> {
+    let repo_owner = pr.repo_owner()?;
+    let username = github_settings.username.as_str();
+
+    let assignments = Assignments {
+        requested_reviewers: details
+            .requested_reviewers
+            .iter()
+            .map(|x| x.login.clone())
+            .collect(),
+        requested_teams: details
+            .requested_teams
+            .iter()
+            .map(|x| format!("{}/{}", repo_owner, x.slug))
+            .collect(),
+        assignees: details.assignees.iter().map(|x| x.login.clone()).collect(),
+    };
+
+    let empty = Assignments::default();
+    let previous = match current {
+        // new PRs have nothing to compare against, everything is new
+        None => &empty,
+        Some(PullRequest {
+            assignments: Some(previous),
+            ..
+        }) => previous,
+        // PRs cached before assignments were tracked would all look new
+        Some(_) => return Ok((assignments, Vec::default())),
+    };
+
+    let mut ret = Vec::default();
+
+    let is_newly_requested = |x: &str| {
+        assignments.requested_reviewers.contains(x) && !previous.requested_reviewers.contains(x)
+    };
+    if is_newly_requested(username) {
+        ret.push(match has_reviewed {
+            true => Assignment::ReReviewRequested,
+            false => Assignment::ReviewRequested,
+        });
+    } else if !assignments.requested_reviewers.contains(username) && pr.user.login != username {
+        // a request for the user supersedes requests for their teams
+        for team in &assignments.requested_teams {
+            let is_own_team = github_settings
+                .teams
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(team));
+            if is_own_team && !previous.requested_teams.contains(team) {
+                ret.push(Assignment::TeamReviewRequested { team: team.clone() });
+            }
+        }
+    }
+
+    if assignments.assignees.contains(username) && !previous.assignees.contains(username) {
+        ret.push(Assignment::Assigned);
+    }
+
+    Ok((assignments, ret))
+}
+
+/// Returns whether each review thread of the PR is resolved, along with the threads that were
+/// resolved or unresolved since the last poll. Resolutions are sent for threads started by the
+/// user, and unresolutions for threads on the user's own PRs. Threads that could not be fetched
+/// keep their state from the last poll.
+fn threads(
+    pr: &Issue,
+    review_threads: Option<Vec<ReviewThread>>,
+    current: Option<&PullRequest>,
+    username: &str,
+) -> (HashMap<String, bool>, Vec<Thread>) {
+    let Some(review_threads) = review_threads else {
+        let threads = current.and_then(|x| x.threads.clone()).unwrap_or_default();
+        return (threads, Vec::default());
+    };
+
+    let threads = review_threads
+        .iter()
+        .map(|x| (x.id.clone(), x.is_resolved))
         .collect();
 
-    Ok(ret)
+    // new PRs, and PRs cached before threads were tracked, have nothing to compare against
+    let Some(previous) = current.and_then(|x| x.threads.as_ref()) else {
+        return (threads, Vec::default());
+    };
+
+    let mut ret = Vec::default();
+    for review_thread in review_threads {
+        // threads that are new since the last poll can't have changed
+        let Some(was_resolved) = previous.get(&review_thread.id) else {
+            continue;
+        };
+        if *was_resolved == review_thread.is_resolved {
+            continue;
+        }
+
+        let comment = review_thread.comments.nodes.into_iter().next();
+        let comment_author = comment.as_ref().and_then(|x| x.author.as_ref());
+        let resolver = review_thread.resolved_by.map(|x| x.login);
+        let notify = match review_thread.is_resolved {
+            true => {
+                comment_author.map(|x| x.login.as_str()) == Some(username)
+                    && resolver.as_deref() != Some(username)
+            }
+            false => pr.user.login == username,
+        };
+        if !notify {
+            continue;
+        }
+
+        let location = match review_thread.line.or(review_thread.original_line) {
+            Some(line) => format!("{}:{}", review_thread.path, line),
+            None => review_thread.path,
+        };
+        let comment = comment.map(|x| {
+            let author = x.author.map_or_else(|| "ghost".to_owned(), |y| y.login);
+            Comment::new(author, x.body, x.created_at, pr.html_url.clone(), x.url)
+        });
+        let resolution = match review_thread.is_resolved {
+            true => Resolution::Resolved,
+            false => Resolution::Unresolved,
+        };
+
+        ret.push(Thread::new(
+            resolver,
+            resolution,
+            location,
+            comment,
+            pr.html_url.clone(),
+        ));
+    }
+
+    (threads, ret)
 }
 
-async fn send_notifications(
-    ntfy_client: &NtfyClient,
+/// Returns the notifications to send for a PR, in the order they should be sent
+#[allow(clippy::too_many_arguments)]
+fn notifications(
+    mention_pattern: &Regex,
+    notify_deleted_comments: bool,
     current_data: &HashMap<usize, PullRequest>,
     reviews_by_ids: &HashMap<usize, Review>,
     comments_by_ids: &HashMap<usize, Comment>,
+    comment_ids: &HashSet<usize>,
+    replies_by_ids: &HashMap<usize, Reply>,
+    updates: Vec<EventKind>,
     pr: &Issue,
-) -> Result<()> {
+) -> Result<Vec<Event>> {
+    let pull_request = event::PullRequest::try_from(pr)?;
+
     if !current_data.contains_key(&pr.id) {
-        debug!("Sending notification for new pr: {:?}", pr);
-        ntfy_client
-            .notify(
-                "New Pull Request",
-                format!("@{} opened {}", pr.user.login, pr.title),
-                &[("Open PR", &pr.html_url)],
-            )
-            .await?;
+        // review requests and assignments on a new PR are more specific than the new PR itself,
+        // send them instead if there are any
+        if updates.is_empty() {
+            debug!("Sending notification for new pr: {:?}", pr);
+            let event = Event::new(pull_request, EventKind::NewPullRequest);
+            return Ok(vec![event]);
+        }
 
         // this is a new PR, no need to check comments or reviews
-        return Ok(());
+        return Ok(updates
+            .into_iter()
+            .map(|x| {
+                debug!("Sending notification for new pr: {:?}", x);
+                Event::new(pull_request.clone(), x)
+            })
+            .collect());
     }
 
     let current = &current_data[&pr.id];
-    for (k, v) in comments_by_ids {
-        if !current.comments.contains(&k) {
-            debug!("Sending notification for comment: {:?}", v);
-            ntfy_client
-                .notify(
-                    &pr.title,
-                    v.to_string(),
-                    &[("Open PR", &v.pr_url), ("Open Comment", &v.url)],
-                )
-                .await?;
+
+    // new comments, reviews and replies are sent in the order they were posted. Edits, deletions
+    // and updates have no time of their own, they follow in the order they were found. Ids
+    // increase over time, so iterating by id keeps that order the same from poll to poll.
+    let mut timeline: Vec<(Option<&str>, Event)> = Vec::default();
+
+    for (k, v) in sorted_by_ids(comments_by_ids) {
+        match current.comments.get(k) {
+            None => {
+                debug!("Sending notification for comment: {:?}", v);
+                let mention = mention_pattern.is_match(v.body());
+                let event = Event::new(pull_request.clone(), EventKind::Comment(v.clone()))
+                    .with_mention(mention);
+                timeline.push((Some(v.created_at()), event));
+            }
+            Some(Some(previous)) if previous.body != v.body() => {
+                let edit = Edit::new(
+                    v.author().to_owned(),
+                    Subject::Comment,
+                    previous.body.clone(),
+                    v.body().to_owned(),
+                    v.pr_url.clone(),
+                    v.url.clone(),
+                );
+                debug!("Sending notification for comment edit: {:?}", edit);
+                // only mentions that were added by the edit are worth raising again
+                let mention =
+                    mention_pattern.is_match(v.body()) && !mention_pattern.is_match(&previous.body);
+                let event =
+                    Event::new(pull_request.clone(), EventKind::Edit(edit)).with_mention(mention);
+                timeline.push((None, event));
+            }
+            // unchanged, or cached before contents were tracked
+            Some(_) => {}
         }
     }
 
-    for (k, v) in reviews_by_ids {
-        if !current.reviews.contains(&k) {
-            debug!("Sending notification for review: {:?}", v);
-            ntfy_client
-                .notify(
-                    &pr.title,
-                    v.to_string(),
-                    &[("Open PR", &v.pr_url), ("Open Comment", &v.url)],
-                )
-                .await?;
+    if notify_deleted_comments {
+        for (k, v) in sorted_by_ids(&current.comments) {
+            // comments cached before contents were tracked can't say what was deleted
+            let Some(previous) = v else {
+                continue;
+            };
+            if !comment_ids.contains(k) {
+                let deletion = Deletion::new(previous.author.clone(), previous.body.clone());
+                debug!("Sending notification for comment deletion: {:?}", deletion);
+                let event = Event::new(pull_request.clone(), EventKind::Deletion(deletion));
+                timeline.push((None, event));
+            }
         }
     }
 
-    Ok(())
+    for (k, v) in sorted_by_ids(reviews_by_ids) {
+        match current.reviews.get(k) {
+            None => {
+                debug!("Sending notification for review: {:?}", v);
+                let mention = v
+                    .body()
+                    .into_iter()
+                    .chain(v.comments().iter().map(|x| x.body.as_str()))
+                    .any(|x| mention_pattern.is_match(x));
+                let event = Event::new(pull_request.clone(), EventKind::Review(v.clone()))
+                    .with_mention(mention);
+                timeline.push((Some(v.submitted_at()), event));
+            }
+            Some(Some(previous)) if previous.body != v.body().unwrap_or_default() => {
+                let body = v.body().unwrap_or_default();
+                let edit = Edit::new(
+                    v.author().to_owned(),
+                    Subject::Review,
+                    previous.body.clone(),
+                    body.to_owned(),
+                    v.pr_url.clone(),
+                    v.url.clone(),
+                );
+                debug!("Sending notification for review edit: {:?}", edit);
+                let mention =
+                    mention_pattern.is_match(body) && !mention_pattern.is_match(&previous.body);
+                let event =
+                    Event::new(pull_request.clone(), EventKind::Edit(edit)).with_mention(mention);
+                timeline.push((None, event));
+            }
+            Some(_) => {}
+        }
+    }
+
+    // PRs cached before replies were tracked would have all of their replies look new
+    if let Some(replies) = &current.replies {
+        for (k, v) in sorted_by_ids(replies_by_ids) {
+            if !replies.contains(k) {
+                debug!("Sending notification for reply: {:?}", v);
+                let mention = mention_pattern.is_match(&v.comment().body);
+                let event = Event::new(pull_request.clone(), EventKind::Reply(v.clone()))
+                    .with_mention(mention);
+                timeline.push((Some(v.created_at()), event));
+            }
+        }
+    }
+
+    for update in updates {
+        debug!("Sending notification for update: {:?}", update);
+        let event = Event::new(pull_request.clone(), update);
+        timeline.push((None, event));
+    }
+
+    // ISO 8601 timestamps in UTC sort chronologically as strings. The sort is stable, so events
+    // without a time keep their order after the ones with a time.
+    timeline.sort_by_key(|(time, _)| (time.is_none(), *time));
+
+    Ok(timeline.into_iter().map(|(_, event)| event).collect())
 }
 
-fn is_comment_filtered(body: &str, exclude_comment_patterns: &Vec<Regex>) -> bool {
+/// Returns the entries of the map ordered by their ids
+fn sorted_by_ids<V>(map: &HashMap<usize, V>) -> Vec<(&usize, &V)> {
+    let mut ret: Vec<(&usize, &V)> = map.iter().collect();
+    ret.sort_by_key(|(k, _)| **k);
+    ret
+}
+
+/// Returns a pattern that matches mentions of the user or any of their teams
+fn mention_pattern(github_settings: &settings::Github) -> Result<Regex> {
+    let handles = std::iter::once(&github_settings.username)
+        .chain(&github_settings.teams)
+        .map(|x| regex::escape(x))
+        .collect::<Vec<String>>()
+        .join("|");
+
+    // the mention must not be part of a longer handle or an email address
+    Ok(Regex::new(&format!(
+        r"(?i)(?:^|[^\w@/.-])@(?:{})(?:$|[^\w/-])",
+        handles
+    ))?)
+}
+
+fn is_comment_filtered(body: &str, exclude_comment_patterns: &[Regex]) -> bool {
     exclude_comment_patterns.iter().any(|x| x.is_match(body))
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const PR_URL: &str = "https://github.com/org/repo/pull/1";
+
+    fn issue(author: &str) -> Issue {
+        serde_json::from_value(json!({
+            "id": 1,
+            "number": 1,
+            "title": "Add a feature",
+            "user": { "login": author },
+            "html_url": PR_URL,
+            "updated_at": "2023-06-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    /// Returns the details of an open PR, with the given fields replaced
+    fn details(fields: Value) -> github::PullRequest {
+        let mut value = json!({
+            "head": { "sha": "head" },
+            "state": "open",
+            "draft": false,
+            "merged_at": null,
+            "merged_by": null,
+        });
+        value.as_object_mut().unwrap().extend(
+            fields
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Returns a cached PR without comments or reviews, with the given fields
+    fn cached(fields: Value) -> PullRequest {
+        let mut value = json!({ "reviews": [], "comments": [] });
+        value.as_object_mut().unwrap().extend(
+            fields
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn state_change_detects_transitions() {
+        let open = cached(json!({ "state": "open" }));
+        let merged = details(json!({
+            "state": "closed",
+            "merged_at": "2023-06-02T00:00:00Z",
+            "merged_by": { "login": "maintainer" },
+        }));
+        let change = state_change(Some(&open), &merged, "me").unwrap();
+        assert_eq!(change.headline(), "PR merged by @maintainer");
+
+        let closed = details(json!({ "state": "closed" }));
+        let change = state_change(Some(&open), &closed, "me").unwrap();
+        assert!(matches!(change.transition(), Transition::Closed));
+        assert_eq!(change.author(), None);
+
+        let reopened = details(json!({}));
+        let change =
+            state_change(Some(&cached(json!({ "state": "merged" }))), &reopened, "me").unwrap();
+        assert!(matches!(change.transition(), Transition::Reopened));
+    }
+
+    #[test]
+    fn state_change_detects_drafts() {
+        let open = cached(json!({ "state": "open" }));
+        let draft = details(json!({ "draft": true }));
+        let change = state_change(Some(&open), &draft, "me").unwrap();
+        assert!(matches!(change.transition(), Transition::ConvertedToDraft));
+
+        let open_draft = cached(json!({ "state": "open", "draft": true }));
+        let change = state_change(Some(&open_draft), &details(json!({})), "me").unwrap();
+        assert!(matches!(change.transition(), Transition::ReadyForReview));
+    }
+
+    #[test]
+    fn state_change_ignores_unknown_and_own_changes() {
+        let merged_by_me = details(json!({
+            "state": "closed",
+            "merged_at": "2023-06-02T00:00:00Z",
+            "merged_by": { "login": "me" },
+        }));
+
+        // new PRs, and PRs cached before states were tracked
+        assert!(state_change(None, &merged_by_me, "me").is_none());
+        assert!(state_change(Some(&cached(json!({}))), &merged_by_me, "me").is_none());
+
+        let open = cached(json!({ "state": "open" }));
+        assert!(state_change(Some(&open), &merged_by_me, "me").is_none());
+        assert!(state_change(Some(&open), &details(json!({})), "me").is_none());
+    }
+
+    fn statuses(statuses: Value) -> Option<CombinedStatus> {
+        Some(serde_json::from_value(json!({ "statuses": statuses })).unwrap())
+    }
+
+    fn check_runs(check_runs: Value) -> Option<CheckRunsResponse> {
+        Some(serde_json::from_value(json!({ "check_runs": check_runs })).unwrap())
+    }
+
+    #[test]
+    fn ci_is_only_checked_for_own_prs() {
+        let (status, sent) = ci(
+            &issue("someone"),
+            &details(json!({})),
+            statuses(json!([{ "state": "failure", "context": "build" }])),
+            None,
+            None,
+            "me",
+        );
+        assert!(status.is_none());
+        assert!(sent.is_none());
+    }
+
+    #[test]
+    fn ci_is_not_sent_when_first_seen() {
+        let (status, sent) = ci(
+            &issue("me"),
+            &details(json!({})),
+            statuses(json!([{ "state": "success", "context": "build" }])),
+            None,
+            Some(&cached(json!({}))),
+            "me",
+        );
+        let status = status.unwrap();
+        assert_eq!(status.sha, "head");
+        assert_eq!(status.status, CiStatus::Success);
+        assert!(sent.is_none());
+    }
+
+    #[test]
+    fn ci_failures_are_sent_without_waiting_for_pending_checks() {
+        let current = cached(json!({ "ci": { "sha": "head", "status": "pending" } }));
+        let (status, sent) = ci(
+            &issue("me"),
+            &details(json!({})),
+            statuses(json!([{ "state": "pending", "context": "deploy" }])),
+            check_runs(json!([
+                { "name": "lint", "status": "completed", "conclusion": "success" },
+                { "name": "test", "status": "completed", "conclusion": "failure" },
+            ])),
+            Some(&current),
+            "me",
+        );
+        assert_eq!(status.unwrap().status, CiStatus::Failure);
+        let sent = sent.unwrap();
+        assert_eq!(sent.status(), CiStatus::Failure);
+        assert_eq!(sent.failed_checks(), ["test".to_owned()]);
+    }
+
+    #[test]
+    fn ci_is_sent_once_it_finishes() {
+        let pending = cached(json!({ "ci": { "sha": "head", "status": "pending" } }));
+        let in_progress = check_runs(json!([{ "name": "test", "status": "in_progress" }]));
+        let finished = || check_runs(json!([{ "name": "test", "status": "completed" }]));
+
+        let (status, sent) = ci(
+            &issue("me"),
+            &details(json!({})),
+            None,
+            in_progress,
+            Some(&pending),
+            "me",
+        );
+        assert_eq!(status.unwrap().status, CiStatus::Pending);
+        assert!(sent.is_none());
+
+        let (_, sent) = ci(
+            &issue("me"),
+            &details(json!({})),
+            None,
+            finished(),
+            Some(&pending),
+            "me",
+        );
+        assert_eq!(sent.unwrap().status(), CiStatus::Success);
+
+        // the result was already sent
+        let passed = cached(json!({ "ci": { "sha": "head", "status": "success" } }));
+        let (_, sent) = ci(
+            &issue("me"),
+            &details(json!({})),
+            None,
+            finished(),
+            Some(&passed),
+            "me",
+        );
+        assert!(sent.is_none());
+
+        // CI of the new head after a push
+        let (_, sent) = ci(
+            &issue("me"),
+            &details(json!({ "head": { "sha": "pushed" } })),
+            None,
+            finished(),
+            Some(&passed),
+            "me",
+        );
+        assert_eq!(sent.unwrap().status(), CiStatus::Success);
+    }
+
+    fn github_settings(teams: &[&str]) -> settings::Github {
+        serde_json::from_value(json!({
+            "personal_access_token": "token",
+            "hostname": "github.com",
+            "username": "me",
+            "queries": [],
+            "low_priority_queries": [],
+            "rate_limit_reserve": 0,
+            "exclude_comment_patterns": [],
+            "teams": teams,
+            "notify_deleted_comments": false,
+            "fetcher": "rest",
+            "concurrency": 1,
+        }))
+        .unwrap()
+    }
+
+    fn headlines(assignments: &[Assignment]) -> Vec<String> {
+        assignments.iter().map(|x| x.headline()).collect()
+    }
+
+    #[test]
+    fn assignments_on_new_prs() {
+        let requested = details(json!({
+            "requested_reviewers": [{ "login": "me" }, { "login": "someone" }],
+            "requested_teams": [{ "slug": "backend" }],
+            "assignees": [{ "login": "me" }],
+        }));
+
+        let (assignments, ret) = assignments(
+            &issue("author"),
+            &requested,
+            None,
+            false,
+            &github_settings(&["org/backend"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            assignments.requested_reviewers,
+            HashSet::from(["me".to_owned(), "someone".to_owned()])
+        );
+        assert_eq!(
+            assignments.requested_teams,
+            HashSet::from(["org/backend".to_owned()])
+        );
+        // the request for the user supersedes the one for their team
+        assert_eq!(
+            headlines(&ret),
+            vec![
+                Assignment::ReviewRequested.headline(),
+                Assignment::Assigned.headline()
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_only_new_requests_are_sent() {
+        let requested = details(json!({ "requested_reviewers": [{ "login": "me" }] }));
+        let current = cached(json!({
+            "assignments": {
+                "requested_reviewers": ["me"],
+                "requested_teams": [],
+                "assignees": [],
+            },
+        }));
+
+        let (_, ret) = assignments(
+            &issue("author"),
+            &requested,
+            Some(&current),
+            false,
+            &github_settings(&[]),
+        )
+        .unwrap();
+        assert!(ret.is_empty());
+
+        // PRs cached before assignments were tracked
+        let (_, ret) = assignments(
+            &issue("author"),
+            &requested,
+            Some(&cached(json!({}))),
+            false,
+            &github_settings(&[]),
+        )
+        .unwrap();
+        assert!(ret.is_empty());
+    }
+
+    #[test]
+    fn assignments_re_review_requests() {
+        let requested = details(json!({ "requested_reviewers": [{ "login": "me" }] }));
+        let current = cached(json!({
+            "assignments": { "requested_reviewers": [], "requested_teams": [], "assignees": [] },
+        }));
+
+        let (_, ret) = assignments(
+            &issue("author"),
+            &requested,
+            Some(&current),
+            true,
+            &github_settings(&[]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            headlines(&ret),
+            vec![Assignment::ReReviewRequested.headline()]
+        );
+    }
+
+    #[test]
+    fn assignments_team_requests() {
+        let requested = details(json!({
+            "requested_teams": [{ "slug": "backend" }, { "slug": "frontend" }],
+        }));
+
+        let (_, ret) = assignments(
+            &issue("author"),
+            &requested,
+            None,
+            false,
+            &github_settings(&["Org/Backend"]),
+        )
+        .unwrap();
+        assert_eq!(
+            headlines(&ret),
+            vec![Assignment::TeamReviewRequested {
+                team: "org/backend".to_owned()
+            }
+            .headline()]
+        );
+
+        // the user's own PRs don't need their team's review
+        let (_, ret) = assignments(
+            &issue("me"),
+            &requested,
+            None,
+            false,
+            &github_settings(&["org/backend"]),
+        )
+        .unwrap();
+        assert!(ret.is_empty());
+    }
+
+    #[test]
+    fn mention_pattern_matches_the_user_and_their_teams() {
+        let pattern = mention_pattern(&github_settings(&["org/backend"])).unwrap();
+
+        for body in [
+            "@me",
+            "cc @me",
+            "thanks @Me, looks good",
+            "(@me)",
+            "@org/backend can you take a look?",
+        ] {
+            assert!(pattern.is_match(body), "{}", body);
+        }
+
+        for body in [
+            "me",
+            "@meow",
+            "@me-too",
+            "someone@me.com",
+            "@org/backend-ops",
+            "github.com/@me",
+        ] {
+            assert!(!pattern.is_match(body), "{}", body);
+        }
+    }
+
+    fn comment(author: &str, body: &str, created_at: &str) -> Comment {
+        Comment::new(
+            author.to_owned(),
+            body.to_owned(),
+            created_at.to_owned(),
+            PR_URL.to_owned(),
+            format!("{}#issuecomment", PR_URL),
+        )
+    }
+
+    fn names(events: &[Event]) -> Vec<&'static str> {
+        events.iter().map(|x| x.kind.name()).collect()
+    }
+
+    #[test]
+    fn notifications_comment_edits_and_deletions() {
+        let current_data = HashMap::from([(
+            1,
+            cached(json!({
+                "comments": {
+                    "10": { "author": "bot", "body": "Deployed to staging" },
+                    "11": { "author": "someone", "body": "First" },
+                    "12": { "author": "someone", "body": "Typo" },
+                },
+            })),
+        )]);
+        // the bot's comment is filtered out, it is not part of the comments anymore but it
+        // still exists
+        let comments_by_ids = HashMap::from([(
+            12,
+            comment("someone", "Fixed the typo @me", "2023-06-01T00:00:00Z"),
+        )]);
+        let comment_ids = HashSet::from([10, 12]);
+
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            true,
+            &current_data,
+            &HashMap::default(),
+            &comments_by_ids,
+            &comment_ids,
+            &HashMap::default(),
+            Vec::default(),
+            &issue("me"),
+        )
+        .unwrap();
+
+        assert_eq!(names(&events), vec!["edit", "deletion"]);
+        assert!(events[0].mention);
+        let EventKind::Deletion(deletion) = &events[1].kind else {
+            panic!("expected a deletion");
+        };
+        assert_eq!(deletion.author(), "someone");
+        assert_eq!(deletion.body(), "First");
+
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            false,
+            &current_data,
+            &HashMap::default(),
+            &comments_by_ids,
+            &comment_ids,
+            &HashMap::default(),
+            Vec::default(),
+            &issue("me"),
+        )
+        .unwrap();
+        assert_eq!(names(&events), vec!["edit"]);
+    }
+
+    #[test]
+    fn notifications_are_in_chronological_order() {
+        let current_data = HashMap::from([(
+            1,
+            cached(json!({
+                "comments": { "10": { "author": "someone", "body": "Before" } },
+                "replies": [],
+            })),
+        )]);
+        let comments_by_ids = HashMap::from([
+            (10, comment("someone", "After", "2023-06-01T00:00:00Z")),
+            (11, comment("someone", "Latest", "2023-06-01T03:00:00Z")),
+        ]);
+        let review = Review::new(
+            "reviewer".to_owned(),
+            feedback::ReviewState::Approved,
+            String::default(),
+            "2023-06-01T01:00:00Z".to_owned(),
+            PR_URL.to_owned(),
+            format!("{}#pullrequestreview", PR_URL),
+        );
+        let reply = Reply::new(
+            "reviewer".to_owned(),
+            feedback::ReviewComment {
+                path: "src/main.rs".to_owned(),
+                line: Some(1),
+                diff_hunk: String::default(),
+                in_reply_to_id: Some(20),
+                body: "Done".to_owned(),
+                created_at: "2023-06-01T02:00:00Z".to_owned(),
+            },
+            None,
+            PR_URL.to_owned(),
+            format!("{}#discussion", PR_URL),
+        );
+        let ci = Ci::new(CiStatus::Success, Vec::default(), PR_URL.to_owned());
+
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            false,
+            &current_data,
+            &HashMap::from([(30, review)]),
+            &comments_by_ids,
+            &HashSet::from([10, 11]),
+            &HashMap::from([(21, reply)]),
+            vec![EventKind::Ci(ci)],
+            &issue("me"),
+        )
+        .unwrap();
+
+        // events without a time of their own follow in the order they were found
+        assert_eq!(
+            names(&events),
+            vec!["review", "reply", "comment", "edit", "ci"]
+        );
+    }
+
+    #[test]
+    fn notifications_for_new_prs() {
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            false,
+            &HashMap::default(),
+            &HashMap::default(),
+            &HashMap::from([(10, comment("someone", "Hi", "2023-06-01T00:00:00Z"))]),
+            &HashSet::from([10]),
+            &HashMap::default(),
+            Vec::default(),
+            &issue("someone"),
+        )
+        .unwrap();
+        assert_eq!(names(&events), vec!["new_pull_request"]);
+
+        // a review request is more specific than the new PR itself
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            false,
+            &HashMap::default(),
+            &HashMap::default(),
+            &HashMap::default(),
+            &HashSet::default(),
+            &HashMap::default(),
+            vec![EventKind::Assignment(Assignment::ReviewRequested)],
+            &issue("someone"),
+        )
+        .unwrap();
+        assert_eq!(names(&events), vec!["assignment"]);
+    }
+
+    #[test]
+    fn threads_that_could_not_be_fetched_keep_their_state() {
+        let current = cached(json!({ "threads": { "thread": true } }));
+
+        let (threads, events) = threads(&issue("me"), None, Some(&current), "me");
+
+        assert_eq!(threads, HashMap::from([("thread".to_owned(), true)]));
+        assert!(events.is_empty());
+    }
+
+    /// Returns a thread on line 3 of `src/main.rs` started by `author`, and resolved by `resolver`
+    /// if there is one
+    fn review_thread(author: &str, resolver: Option<&str>) -> ReviewThread {
+        serde_json::from_value(json!({
+            "id": "thread",
+            "isResolved": resolver.is_some(),
+            "resolvedBy": resolver.map(|x| json!({ "login": x })),
+            "path": "src/main.rs",
+            "line": 3,
+            "originalLine": 3,
+            "comments": {
+                "nodes": [{
+                    "author": { "login": author },
+                    "body": "Could this be simpler?",
+                    "url": format!("{}#discussion_r1", PR_URL),
+                    "createdAt": "2023-06-01T00:00:00Z",
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn resolved_threads_notify_their_starter() {
+        let current = cached(json!({ "threads": { "thread": false } }));
+        let review_thread = review_thread("me", Some("octocat"));
+
+        let (threads, events) = threads(
+            &issue("octocat"),
+            Some(vec![review_thread]),
+            Some(&current),
+            "me",
+        );
+
+        assert_eq!(threads, HashMap::from([("thread".to_owned(), true)]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].author(), Some("octocat"));
+        assert_eq!(events[0].resolution(), Resolution::Resolved);
+        assert_eq!(events[0].location(), "src/main.rs:3");
+        assert_eq!(
+            events[0].comment().unwrap().body(),
+            "Could this be simpler?"
+        );
+    }
+
+    #[test]
+    fn threads_resolved_by_their_starter_are_not_notified() {
+        let current = cached(json!({ "threads": { "thread": false } }));
+
+        let (_, events) = threads(
+            &issue("octocat"),
+            Some(vec![review_thread("me", Some("me"))]),
+            Some(&current),
+            "me",
+        );
+        assert!(events.is_empty());
+
+        // nor are threads resolved on PRs of the user, that someone else started
+        let (_, events) = threads(
+            &issue("me"),
+            Some(vec![review_thread("reviewer", Some("me"))]),
+            Some(&current),
+            "me",
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn unresolved_threads_notify_the_author_of_the_pr() {
+        let current = cached(json!({ "threads": { "thread": true } }));
+
+        let (resolved, events) = threads(
+            &issue("me"),
+            Some(vec![review_thread("reviewer", None)]),
+            Some(&current),
+            "me",
+        );
+        assert_eq!(resolved, HashMap::from([("thread".to_owned(), false)]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].author(), None);
+        assert_eq!(events[0].resolution(), Resolution::Unresolved);
+
+        // the starter of the thread isn't notified on PRs of others
+        let (_, events) = threads(
+            &issue("octocat"),
+            Some(vec![review_thread("me", None)]),
+            Some(&current),
+            "me",
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn new_and_unchanged_threads_are_not_notified() {
+        let unchanged = cached(json!({ "threads": { "thread": true } }));
+        let untracked = cached(json!({}));
+        let review_thread = || review_thread("me", Some("octocat"));
+
+        for current in [Some(&unchanged), Some(&untracked), None] {
+            let (_, events) = threads(
+                &issue("octocat"),
+                Some(vec![review_thread()]),
+                current,
+                "me",
+            );
+            assert!(events.is_empty());
+        }
+    }
+
+    #[test]
+    fn low_priority_queries_are_skipped_below_the_reserve() {
+        let mut github_settings = github_settings(&[]);
+        github_settings.low_priority_queries = vec!["is:pr archived:false".to_owned()];
+        github_settings.rate_limit_reserve = 100;
+        let budget = |remaining| {
+            Some(RateLimit {
+                limit: 5000,
+                remaining,
+                reset: 0,
+            })
+        };
+
+        assert!(skip_low_priority(&github_settings, budget(99)));
+        assert!(!skip_low_priority(&github_settings, budget(100)));
+        assert!(!skip_low_priority(&github_settings, None));
+
+        github_settings.low_priority_queries.clear();
+        assert!(!skip_low_priority(&github_settings, budget(0)));
+    }
+
+    #[test]
+    fn only_authentication_failures_are_fatal() {
+        let auth = || GithubError::Auth {
+            message: "Bad credentials".to_owned(),
+        };
+        assert!(is_fatal(&auth().into()));
+        assert!(is_fatal(
+            &anyhow::Error::from(auth()).context("Could not fetch PR")
+        ));
+
+        // a PR that the token can't access only fails that PR
+        let forbidden = GithubError::Forbidden {
+            url: PR_URL.to_owned(),
+            message: "Resource protected by organization SAML enforcement".to_owned(),
+        };
+        assert!(!is_fatal(&forbidden.into()));
+        let not_found = GithubError::NotFound {
+            url: PR_URL.to_owned(),
+        };
+        assert!(!is_fatal(&not_found.into()));
+        let rate_limited = GithubError::RateLimited {
+            resource: "core".to_owned(),
+            reset_in: None,
+        };
+        assert!(!is_fatal(&rate_limited.into()));
+        assert!(!is_fatal(&anyhow!("Missing data")));
+    }
+
+    /// Returns the commits of a PR as the REST API lists them, all authored by `author`
+    fn commits_response(shas: &[&str], author: &str) -> Option<Vec<github::Commit>> {
+        let commits = shas
+            .iter()
+            .map(|x| {
+                json!({
+                    "sha": x,
+                    "commit": { "message": format!("Commit {}\n\nDetails", x) },
+                    "author": { "login": author },
+                })
+            })
+            .collect();
+        Some(serde_json::from_value(Value::Array(commits)).unwrap())
+    }
+
+    fn pushed(push: &Push) -> Vec<&str> {
+        push.commits().iter().map(|x| x.sha.as_str()).collect()
+    }
+
+    #[test]
+    fn commits_are_reused_while_the_head_has_not_moved() {
+        let current = cached(json!({ "head_sha": "a", "commits": ["a"] }));
+
+        let (shas, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "a" } })),
+            None,
+            Some(&current),
+            "me",
+        );
+
+        assert_eq!(shas, vec!["a"]);
+        assert!(push.is_none());
+    }
+
+    #[test]
+    fn commits_of_new_prs_are_not_pushes() {
+        let (shas, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "b" } })),
+            commits_response(&["a", "b"], "someone"),
+            None,
+            "me",
+        );
+
+        assert_eq!(shas, vec!["a", "b"]);
+        assert!(push.is_none());
+    }
+
+    #[test]
+    fn commits_pushed_on_top_of_the_head() {
+        let current = cached(json!({ "head_sha": "a", "commits": ["a"] }));
+
+        let (shas, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "c" } })),
+            commits_response(&["a", "b", "c"], "someone"),
+            Some(&current),
+            "me",
+        );
+
+        assert_eq!(shas, vec!["a", "b", "c"]);
+        let push = push.unwrap();
+        assert!(!push.forced());
+        assert_eq!(pushed(&push), vec!["b", "c"]);
+        assert_eq!(push.headline(), "@someone pushed 2 commits");
+    }
+
+    #[test]
+    fn commits_force_pushed_over_the_head() {
+        let current = cached(json!({ "head_sha": "b", "commits": ["a", "b"] }));
+
+        let (_, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "c" } })),
+            commits_response(&["a", "c"], "someone"),
+            Some(&current),
+            "me",
+        );
+
+        let push = push.unwrap();
+        assert!(push.forced());
+        assert_eq!(pushed(&push), vec!["c"]);
+    }
+
+    #[test]
+    fn commits_force_pushed_back_to_known_ones() {
+        let current = cached(json!({ "head_sha": "b", "commits": ["a", "b"] }));
+
+        let (shas, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "a" } })),
+            commits_response(&["a"], "someone"),
+            Some(&current),
+            "me",
+        );
+
+        assert_eq!(shas, vec!["a"]);
+        let push = push.unwrap();
+        assert!(push.forced());
+        assert!(push.commits().is_empty());
+        assert_eq!(push.headline(), "@someone force-pushed");
+    }
+
+    #[test]
+    fn commits_pushed_by_the_user_are_not_notified() {
+        let current = cached(json!({ "head_sha": "a", "commits": ["a"] }));
+
+        let (shas, push) = commits(
+            &issue("someone"),
+            &details(json!({ "head": { "sha": "b" } })),
+            commits_response(&["a", "b"], "me"),
+            Some(&current),
+            "me",
+        );
+
+        // the commits are still recorded, so that they are not notified later either
+        assert_eq!(shas, vec!["a", "b"]);
+        assert!(push.is_none());
+    }
+
+    /// Returns the reviews and review comments of a thread that "reviewer" started with a comment
+    /// in review 10, and then replied to in review 11 by "someone"
+    fn thread_with_reply() -> (Vec<github::Review>, Vec<github::ReviewComment>) {
+        let review = |id: usize, author: &str| {
+            json!({
+                "id": id,
+                "body": "",
+                "state": "COMMENTED",
+                "user": { "login": author },
+                "html_url": format!("{}#pullrequestreview-{}", PR_URL, id),
+                "submitted_at": "2023-06-01T00:00:00Z",
+            })
+        };
+        let review_comment = |id: usize, review_id: usize, author: &str, in_reply_to_id| {
+            json!({
+                "id": id,
+                "pull_request_review_id": review_id,
+                "path": "src/main.rs",
+                "line": 3,
+                "original_line": 3,
+                "diff_hunk": "@@ -1,3 +1,3 @@",
+                "in_reply_to_id": in_reply_to_id,
+                "body": format!("Comment {}", id),
+                "user": { "login": author },
+                "html_url": format!("{}#discussion_r{}", PR_URL, id),
+                "created_at": "2023-06-01T00:00:00Z",
+            })
+        };
+
+        let reviews = json!([review(10, "reviewer"), review(11, "someone")]);
+        let review_comments = json!([
+            review_comment(100, 10, "reviewer", None),
+            review_comment(101, 11, "someone", Some(100)),
+        ]);
+        (
+            serde_json::from_value(reviews).unwrap(),
+            serde_json::from_value(review_comments).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reviews_that_only_hold_a_reply_are_dropped() {
+        let (reviews, review_comments) = thread_with_reply();
+
+        let replies_by_ids = replies_by_ids(&issue("me"), &review_comments, "me", &[]);
+        let reviews_by_ids = reviews_by_ids(&issue("me"), reviews, review_comments, "me", &[]);
+
+        assert_eq!(reviews_by_ids.keys().collect::<Vec<_>>(), vec![&10]);
+        assert_eq!(reviews_by_ids[&10].comments().len(), 1);
+        let reply = &replies_by_ids[&101];
+        assert_eq!(reply.author(), "someone");
+        assert_eq!(reply.parent().unwrap().body(), "Comment 100");
+    }
+
+    #[test]
+    fn replies_to_a_cached_review_are_notified() {
+        let (reviews, review_comments) = thread_with_reply();
+        let replies_by_ids = replies_by_ids(&issue("me"), &review_comments, "me", &[]);
+        let reviews_by_ids = reviews_by_ids(&issue("me"), reviews, review_comments, "me", &[]);
+        // the review that started the thread was notified in an earlier poll
+        let current_data = HashMap::from([(
+            1,
+            cached(json!({
+                "reviews": { "10": { "author": "reviewer", "body": "" } },
+                "replies": [],
+            })),
+        )]);
+
+        let events = notifications(
+            &mention_pattern(&github_settings(&[])).unwrap(),
+            false,
+            &current_data,
+            &reviews_by_ids,
+            &HashMap::default(),
+            &HashSet::default(),
+            &replies_by_ids,
+            Vec::default(),
+            &issue("me"),
+        )
+        .unwrap();
+
+        assert_eq!(names(&events), vec!["reply"]);
+    }
+}
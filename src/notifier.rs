@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::error;
+
+use crate::clients::desktop::DesktopClient;
+use crate::clients::discord::DiscordClient;
+use crate::clients::email::EmailClient;
+use crate::clients::matrix::MatrixClient;
+use crate::clients::ntfy::NtfyClient;
+use crate::clients::slack::SlackClient;
+use crate::clients::webhook::WebhookClient;
+use crate::event::Event;
+use crate::settings;
+
+/// A destination that notifications can be sent to
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Returns a short name identifying the notifier in logs
+    fn name(&self) -> &str;
+
+    /// Sends a notification for the given event
+    async fn notify(&self, event: &Event) -> Result<()>;
+
+    /// Called once at the end of every poll, after all the events of the poll have been passed to
+    /// `notify`. Notifiers that batch up events send them here, and hold on to the ones they
+    /// could not send to retry them on the next flush.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the notifiers described by the given settings. The inline comments of reviews are
+/// included in the notifications up to the given limits.
+pub fn from_settings(
+    settings: &[settings::Notifier],
+    review_comments: settings::ReviewComments,
+) -> Result<Vec<Box<dyn Notifier>>> {
+    settings
+        .iter()
+        .map(|x| -> Result<Box<dyn Notifier>> {
+            match x {
+                settings::Notifier::Ntfy(ntfy) => Ok(Box::new(NtfyClient::try_new(
+                    ntfy.base_url.clone(),
+                    ntfy.topic.clone(),
+                    review_comments,
+                )?)),
+                settings::Notifier::Slack(slack) => Ok(Box::new(SlackClient::try_new(
+                    slack.webhook_url.clone(),
+                    review_comments,
+                )?)),
+                settings::Notifier::Webhook(webhook) => Ok(Box::new(WebhookClient::try_new(
+                    webhook.url.clone(),
+                    webhook.secret.clone(),
+                    &webhook.headers,
+                )?)),
+                settings::Notifier::Email(email) => {
+                    Ok(Box::new(EmailClient::try_new(email, review_comments)?))
+                }
+                settings::Notifier::Matrix(matrix) => Ok(Box::new(MatrixClient::try_new(
+                    &matrix.homeserver_url,
+                    matrix.access_token.clone(),
+                    matrix.room_id.clone(),
+                    review_comments,
+                )?)),
+                settings::Notifier::Discord(discord) => Ok(Box::new(DiscordClient::try_new(
+                    discord.webhook_url.clone(),
+                    review_comments,
+                )?)),
+                settings::Notifier::Desktop(desktop) => Ok(Box::new(DesktopClient::new(
+                    desktop.timeout,
+                    review_comments,
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Sends the event to every notifier. A failing notifier does not stop the event from being sent
+/// to the others, an error is only returned if none of them succeeded.
+pub async fn notify_all(notifiers: &[Box<dyn Notifier>], event: &Event) -> Result<()> {
+    let mut failures = 0;
+    for notifier in notifiers {
+        if let Err(e) = notifier.notify(event).await {
+            error!(
+                "Could not send notification to {}: {:?}",
+                notifier.name(),
+                e
+            );
+            failures += 1;
+        }
+    }
+
+    if failures > 0 && failures == notifiers.len() {
+        return Err(anyhow!("Could not send notification to any notifier"));
+    }
+
+    Ok(())
+}
+
+/// Flushes every notifier, even if one of them fails. The events that a notifier could not send
+/// stay batched up in it until the next flush, the error only reports which notifiers failed.
+pub async fn flush_all(notifiers: &[Box<dyn Notifier>]) -> Result<()> {
+    let mut failed = Vec::default();
+    for notifier in notifiers {
+        if let Err(e) = notifier.flush().await {
+            error!(
+                "Could not flush notifications to {}: {:?}",
+                notifier.name(),
+                e
+            );
+            failed.push(notifier.name());
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "Could not flush notifications to {}",
+            failed.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::event::EventKind;
+    use crate::testing::{email_settings, pull_request, SmtpSink, REVIEW_COMMENTS};
+
+    struct Stub {
+        fail: bool,
+        notifies: Arc<AtomicUsize>,
+        flushes: Arc<AtomicUsize>,
+    }
+
+    impl Stub {
+        fn boxed(fail: bool, flushes: &Arc<AtomicUsize>) -> Box<dyn Notifier> {
+            Box::new(Self {
+                fail,
+                notifies: Arc::default(),
+                flushes: flushes.clone(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for Stub {
+        fn name(&self) -> &str {
+            if self.fail {
+                "failing"
+            } else {
+                "working"
+            }
+        }
+
+        async fn notify(&self, _event: &Event) -> Result<()> {
+            self.notifies.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn flush(&self) -> Result<()> {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+            match self.fail {
+                true => Err(anyhow!("flush failed")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_all_succeeds_when_every_notifier_does() {
+        let flushes = Arc::default();
+        let notifiers = [Stub::boxed(false, &flushes), Stub::boxed(false, &flushes)];
+
+        assert!(flush_all(&notifiers).await.is_ok());
+        assert_eq!(flushes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn flush_all_fails_when_any_notifier_does() {
+        let flushes = Arc::default();
+        let notifiers = [Stub::boxed(true, &flushes), Stub::boxed(false, &flushes)];
+
+        let error = flush_all(&notifiers).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not flush notifications to failing"
+        );
+        // a failing notifier does not keep the others from being flushed
+        assert_eq!(flushes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_flush_is_retried_by_the_notifier_alone() {
+        let sink = SmtpSink::start(1).await;
+        let email = EmailClient::try_new(&email_settings(sink.port), REVIEW_COMMENTS).unwrap();
+        let notifies = Arc::new(AtomicUsize::default());
+        let flushes = Arc::new(AtomicUsize::default());
+        let notifiers: [Box<dyn Notifier>; 2] = [
+            Box::new(email),
+            Box::new(Stub {
+                fail: false,
+                notifies: notifies.clone(),
+                flushes: flushes.clone(),
+            }),
+        ];
+        let event = Event::new(pull_request(1), EventKind::NewPullRequest);
+
+        // the first poll sends the event, but the digest is refused
+        notify_all(&notifiers, &event).await.unwrap();
+        assert!(flush_all(&notifiers).await.is_err());
+
+        // the next poll has nothing new, only the digest is sent again
+        flush_all(&notifiers).await.unwrap();
+
+        assert_eq!(notifies.load(Ordering::SeqCst), 1);
+        assert_eq!(flushes.load(Ordering::SeqCst), 2);
+        let messages = sink.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Add a feature"));
+    }
+}
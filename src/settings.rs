@@ -1,10 +1,30 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::{anyhow, Result};
 use config::{Config, Environment, File};
 use directories::ProjectDirs;
 use serde::Deserialize;
 
+/// How the comments, reviews and other data of each PR are fetched
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fetcher {
+    /// Several REST calls per PR
+    Rest,
+    /// A single GraphQL query per batch of PRs
+    Graphql,
+}
+
+/// Where the state of the watched PRs is stored between polls
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheBackend {
+    /// A single JSON file that is rewritten after every poll
+    Json,
+    /// An SQLite database that is updated one PR at a time, with a log of the sent notifications
+    Sqlite,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Github {
     pub personal_access_token: String,
@@ -12,7 +32,13 @@ pub struct Github {
     pub username: String,
     pub proxy_url: Option<String>,
     pub queries: Vec<String>,
+    pub low_priority_queries: Vec<String>,
+    pub rate_limit_reserve: u64,
     pub exclude_comment_patterns: Vec<String>,
+    pub teams: Vec<String>,
+    pub notify_deleted_comments: bool,
+    pub fetcher: Fetcher,
+    pub concurrency: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,9 +47,97 @@ pub struct Ntfy {
     pub topic: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Slack {
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Connect in plain text and upgrade the connection with STARTTLS
+    #[default]
+    Starttls,
+    /// Connect with TLS from the start
+    Tls,
+    /// Do not encrypt the connection, only meant for local mail servers
+    None,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Email {
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub digest: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Matrix {
+    pub homeserver_url: String,
+    pub access_token: String,
+    pub room_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Discord {
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Desktop {
+    pub timeout: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Notifier {
+    Ntfy(Ntfy),
+    Slack(Slack),
+    Webhook(Webhook),
+    Email(Email),
+    Matrix(Matrix),
+    Discord(Discord),
+    Desktop(Desktop),
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Cache {
+    pub backend: CacheBackend,
     pub path: String,
+    // a JSON cache to import into a new SQLite database
+    pub import_path: Option<String>,
+    // whether to only record the state of the PRs, without notifying, when there is no cache
+    pub baseline: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Poll {
+    pub daemon: bool,
+    pub interval: u64,
+    pub jitter: u64,
+}
+
+/// Limits on how much of the inline comments of a review is included in notifications
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ReviewComments {
+    pub max_comments: usize,
+    pub max_hunk_lines: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,8 +148,12 @@ pub struct Firefox {
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub github: Github,
-    pub ntfy: Ntfy,
+    pub ntfy: Option<Ntfy>,
+    #[serde(default)]
+    pub notifiers: Vec<Notifier>,
     pub cache: Cache,
+    pub poll: Poll,
+    pub review_comments: ReviewComments,
     pub firefox: Option<Firefox>,
 }
 
@@ -80,18 +198,45 @@ impl Settings {
         );
         builder = builder.set_default("github.hostname", "api.github.com")?;
         builder = builder.set_default("github.queries", vec!["is:open is:pr involves:@me"])?;
-        builder = builder.set_default::<&str, Vec<&str>>("github.exclude_comment_patterns", vec![])?;
+        builder = builder.set_default::<&str, Vec<&str>>("github.low_priority_queries", vec![])?;
+        builder = builder.set_default("github.rate_limit_reserve", 1000)?;
+        builder =
+            builder.set_default::<&str, Vec<&str>>("github.exclude_comment_patterns", vec![])?;
+        builder = builder.set_default::<&str, Vec<&str>>("github.teams", vec![])?;
+        builder = builder.set_default("github.notify_deleted_comments", false)?;
+        builder = builder.set_default("github.fetcher", "rest")?;
+        builder = builder.set_default("github.concurrency", 8)?;
+        builder = builder.set_default("cache.backend", "json")?;
+        builder = builder.set_default("cache.baseline", true)?;
+        builder = builder.set_default("poll.daemon", false)?;
+        builder = builder.set_default("poll.interval", 300)?;
+        builder = builder.set_default("poll.jitter", 0)?;
+        builder = builder.set_default("review_comments.max_comments", 5)?;
+        builder = builder.set_default("review_comments.max_hunk_lines", 4)?;
         let config = builder.build()?;
 
         let mut settings: Settings = config.try_deserialize()?;
 
         // normalize all the paths
         settings.cache.path = Self::normalize_path(&settings.cache.path)?;
+        if let Some(import_path) = settings.cache.import_path.as_mut() {
+            *import_path = Self::normalize_path(import_path)?;
+        }
 
         if let Some(firefox) = settings.firefox.as_mut() {
             firefox.cookies_file_path = Self::normalize_path(&firefox.cookies_file_path)?;
         }
 
+        // the standalone ntfy section is kept for backwards compatibility, treat it as one more
+        // notifier
+        if let Some(ntfy) = settings.ntfy.take() {
+            settings.notifiers.push(Notifier::Ntfy(ntfy));
+        }
+
+        if settings.notifiers.is_empty() {
+            return Err(anyhow!("At least one notifier must be configured"));
+        }
+
         Ok(settings)
     }
 }
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::event::{Event, EventKind, PullRequest};
+use crate::feedback::Comment;
+use crate::settings;
+
+pub const REVIEW_COMMENTS: settings::ReviewComments = settings::ReviewComments {
+    max_comments: 5,
+    max_hunk_lines: 4,
+};
+
+pub fn pull_request(number: usize) -> PullRequest {
+    PullRequest {
+        id: number,
+        number,
+        title: "Add a feature".to_owned(),
+        url: format!("https://github.com/org/repo/pull/{}", number),
+        repo_owner: "org".to_owned(),
+        repo_name: "repo".to_owned(),
+        author: "octocat".to_owned(),
+    }
+}
+
+/// Returns an event for a comment by "reviewer" on the PR with the given number
+pub fn comment(number: usize, body: &str) -> Event {
+    let pull_request = pull_request(number);
+    let comment = Comment::new(
+        "reviewer".to_owned(),
+        body.to_owned(),
+        "2023-06-01T00:00:00Z".to_owned(),
+        pull_request.url.clone(),
+        format!("{}#issuecomment-1", pull_request.url),
+    );
+    Event::new(pull_request, EventKind::Comment(comment))
+}
+
+/// Returns the settings of an email notifier that sends digests to the local port without TLS
+pub fn email_settings(port: u16) -> settings::Email {
+    settings::Email {
+        host: "127.0.0.1".to_owned(),
+        port: Some(port),
+        security: settings::SmtpSecurity::None,
+        username: None,
+        password: None,
+        from: "prnotify <prnotify@example.com>".to_owned(),
+        to: vec!["octocat@example.com".to_owned()],
+        digest: true,
+    }
+}
+
+/// Returns a path in the temporary directory that is unique to the test
+pub fn temp_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("prnotify-{}-{}", process::id(), name));
+    path.to_string_lossy().into_owned()
+}
+
+/// A request received by the [`Server`]
+#[derive(Clone)]
+pub struct Request {
+    // lowercased, to match header names regardless of their case
+    pub head: String,
+    pub body: String,
+}
+
+/// A stand-in for an HTTP API, that answers the requests in order with the given responses and
+/// keeps the requests it received
+pub struct Server {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    /// Starts the server. `{url}` in the headers of the responses is replaced with the url of the
+    /// server, e.g. for links to the next page.
+    pub async fn start(responses: Vec<String>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::default()));
+        let responses = responses.into_iter().map(|x| x.replace("{url}", &url));
+        let responses = Arc::new(Mutex::new(responses.collect::<VecDeque<String>>()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let received = received.clone();
+                let responses = responses.clone();
+                tokio::spawn(async move {
+                    let mut buffer: Vec<u8> = Vec::default();
+                    let mut chunk = [0; 4096];
+                    while let Ok(len @ 1..) = stream.read(&mut chunk).await {
+                        buffer.extend_from_slice(&chunk[..len]);
+                        while let Some(request) = Self::parse(&mut buffer) {
+                            received.lock().unwrap().push(request);
+                            let response = responses.lock().unwrap().pop_front().unwrap();
+                            stream.write_all(response.as_bytes()).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// Takes the first request out of the buffer once all of it was received
+    fn parse(buffer: &mut Vec<u8>) -> Option<Request> {
+        let end = buffer.windows(4).position(|x| x == b"\r\n\r\n")? + 4;
+        let head = String::from_utf8_lossy(&buffer[..end]).to_lowercase();
+        let len = head
+            .lines()
+            .find_map(|x| x.strip_prefix("content-length:"))
+            .map_or(0, |x| x.trim().parse().unwrap());
+        if buffer.len() < end + len {
+            return None;
+        }
+
+        let body = String::from_utf8_lossy(&buffer[end..end + len]).into_owned();
+        buffer.drain(..end + len);
+        Some(Request { head, body })
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Returns an HTTP response with the given status, headers and body
+pub fn response(status: u16, headers: &[(&str, &str)], body: &str) -> String {
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    format!(
+        "HTTP/1.1 {} Status\r\ncontent-length: {}\r\n{}\r\n{}",
+        status,
+        body.len(),
+        headers,
+        body
+    )
+}
+
+/// A stand-in for an SMTP server that keeps the messages it accepts. The first `reject` messages
+/// are refused after their data was sent.
+pub struct SmtpSink {
+    pub port: u16,
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl SmtpSink {
+    pub async fn start(reject: usize) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages = Arc::new(Mutex::new(Vec::default()));
+        let rejections = Arc::new(Mutex::new(reject));
+
+        let accepted = messages.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let accepted = accepted.clone();
+                let rejections = rejections.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 localhost\r\n").await.unwrap();
+
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_uppercase();
+                        let reply = if command.starts_with("DATA") {
+                            writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                            let mut message = Vec::default();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                message.push(line);
+                            }
+
+                            let mut rejections = rejections.lock().unwrap();
+                            if *rejections > 0 {
+                                *rejections -= 1;
+                                "554 Rejected\r\n"
+                            } else {
+                                accepted.lock().unwrap().push(message.join("\n"));
+                                "250 Accepted\r\n"
+                            }
+                        } else if command.starts_with("QUIT") {
+                            writer.write_all(b"221 Bye\r\n").await.unwrap();
+                            return;
+                        } else {
+                            "250 OK\r\n"
+                        };
+                        writer.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        Self { port, messages }
+    }
+
+    /// Returns the accepted messages, headers and body, in the order they were sent
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
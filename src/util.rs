@@ -9,7 +9,7 @@ pub fn extract_cookies(cookies_file_path: &str, hostname: &str) -> Result<String
     let cookies_filename_temp = temp_path
         .to_str()
         .ok_or_else(|| anyhow!("Could not get temp file path for cookies extraction"))?;
-    fs::copy(cookies_file_path, &cookies_filename_temp)?;
+    fs::copy(cookies_file_path, cookies_filename_temp)?;
 
     let conn = Connection::open(cookies_filename_temp)?;
     let mut stmt = conn.prepare(&format!(
@@ -35,3 +35,11 @@ pub fn extract_cookies(cookies_file_path: &str, hostname: &str) -> Result<String
 
     Ok(cookies)
 }
+
+/// Escapes the characters that have a special meaning in html
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}